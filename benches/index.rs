@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use markdown_db::index::{Field, Index, SearchOptions};
+use markdown_db::test::TestDir;
+
+const DOCUMENTS: usize = 500;
+const WORDS: [&str; 8] = ["markdown", "index", "search", "vault", "note", "link", "tag", "query"];
+
+/// A vault of documents ranging from a single line to a few hundred, with front matter, tags and
+/// links so every part of parsing is exercised.
+fn vault() -> TestDir {
+    let dir = TestDir::new();
+    for n in 0..DOCUMENTS {
+        let mut contents =
+            format!("---\ntitle: Document {n}\ntags: [bench]\n---\n\n# Document {n}\n");
+        for line in 0..(n % 50) * 5 {
+            let word = WORDS[(n + line) % WORDS.len()];
+            contents.push_str(&format!(
+                "A line about {word} with #{word} and a [[Document {}]] link.\n\n",
+                (n + line) % DOCUMENTS
+            ));
+        }
+        dir.write(&format!("folder-{}/document-{n}.md", n % 10), contents).unwrap();
+    }
+    dir
+}
+
+fn refresh(c: &mut Criterion) {
+    let dir = vault();
+
+    c.bench_function("cold refresh", |b| {
+        b.iter_batched(
+            || Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]),
+            |mut index| index.refresh().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+
+    let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+    index.refresh().unwrap();
+    c.bench_function("warm refresh", |b| b.iter(|| index.refresh().unwrap()));
+}
+
+fn search(c: &mut Criterion) {
+    let dir = vault();
+    let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+    index.refresh().unwrap();
+
+    c.bench_function("search word", |b| b.iter(|| index.search("markdown").unwrap()));
+    c.bench_function("search prefix", |b| b.iter(|| index.search("vau").unwrap()));
+    c.bench_function("search tag", |b| b.iter(|| index.search("#bench note").unwrap()));
+    c.bench_function("search missing", |b| b.iter(|| index.search("missing").unwrap()));
+
+    let without_markdown = SearchOptions { fields: Some(Field::defaults()), ..Default::default() };
+    c.bench_function("search word without markdown", |b| {
+        b.iter(|| index.search_with("markdown", &without_markdown).unwrap())
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = refresh, search
+}
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use rusqlite::ErrorCode;
+
+/// The ways a command can fail, for reporting to tools wrapping the cli. Other errors are
+/// sorted into these with `AppError::from`.
+#[derive(Debug)]
+pub enum AppError {
+    /// Obsidian's config, listing its vaults, isn't where it was looked for.
+    ConfigNotFound(PathBuf),
+    /// Another process held the database for longer than the busy timeout.
+    DbLocked(rusqlite::Error),
+    Io(std::io::Error),
+    /// Input that couldn't be read, such as JSON, YAML, a url or a date.
+    Parse(Box<dyn Error>),
+    Other(Box<dyn Error>),
+}
+
+impl AppError {
+    /// A short, stable name for the kind of error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::ConfigNotFound(_) => "config_not_found",
+            AppError::DbLocked(_) => "db_locked",
+            AppError::Io(_) => "io",
+            AppError::Parse(_) => "parse",
+            AppError::Other(_) => "other",
+        }
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::ConfigNotFound(path) => {
+                write!(f, "Obsidian config not found at {}", path.display())
+            }
+            AppError::DbLocked(error) => write!(f, "The database is locked: {error}"),
+            AppError::Io(error) => error.fmt(f),
+            AppError::Parse(error) | AppError::Other(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::ConfigNotFound(_) => None,
+            AppError::DbLocked(error) => Some(error),
+            AppError::Io(error) => Some(error),
+            AppError::Parse(error) | AppError::Other(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for AppError {
+    fn from(error: Box<dyn Error>) -> Self {
+        let error = match error.downcast::<AppError>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<rusqlite::Error>() {
+            Ok(error) => return (*error).into(),
+            Err(error) => error,
+        };
+        let error = match error.downcast::<std::io::Error>() {
+            Ok(error) => return AppError::Io(*error),
+            Err(error) => error,
+        };
+        if error.is::<serde_json::Error>()
+            || error.is::<serde_yaml::Error>()
+            || error.is::<toml::de::Error>()
+            || error.is::<url::ParseError>()
+            || error.is::<chrono::ParseError>()
+        {
+            return AppError::Parse(error);
+        }
+        AppError::Other(error)
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(error: rusqlite::Error) -> Self {
+        match error.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => AppError::DbLocked(error),
+            _ => AppError::Other(Box::new(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(error: impl Into<Box<dyn Error>>) -> &'static str {
+        AppError::from(error.into()).kind()
+    }
+
+    #[test]
+    fn from_tests() {
+        let busy = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        let not_found = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+
+        assert_eq!("db_locked", kind(busy));
+        assert_eq!("other", kind(rusqlite::Error::QueryReturnedNoRows));
+        assert_eq!("io", kind(not_found));
+        assert_eq!("parse", kind(serde_json::from_str::<u8>("x").unwrap_err()));
+        assert_eq!("parse", kind(url::Url::parse("not a url").unwrap_err()));
+        assert_eq!("other", kind("Unknown vault"));
+
+        let config = AppError::ConfigNotFound(PathBuf::from("/config/obsidian.json"));
+        assert_eq!("config_not_found", kind(config));
+        assert_eq!(
+            "Obsidian config not found at /config/obsidian.json",
+            AppError::ConfigNotFound(PathBuf::from("/config/obsidian.json")).to_string()
+        );
+    }
+}
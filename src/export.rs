@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::index::Entry;
+
+/// Writes the markdown of each entry to a file in `out`, named after its title, and returns the
+/// paths written. Titles that would clash, ignoring case, get a counter: `Note.md`, `Note 2.md`.
+/// Unless `overwrite` is set, nothing is written if any of the files already exist, so exporting
+/// into a vault by mistake can't replace its notes.
+pub fn export(entries: &[Entry], out: &Path, overwrite: bool) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(out)?;
+
+    // Entries are taken in uri order, so titles that clash get the same counters each time.
+    let mut entries: Vec<&Entry> = entries.iter().collect();
+    entries.sort_by(|a, b| a.uri().cmp(b.uri()));
+
+    let mut taken = HashSet::new();
+    let mut paths = vec![];
+    for entry in &entries {
+        let name = file_name(entry.title());
+        let mut candidate = name.clone();
+        let mut counter = 1;
+        while !taken.insert(candidate.to_lowercase()) {
+            counter += 1;
+            candidate = format!("{name} {counter}");
+        }
+        paths.push(out.join(format!("{candidate}.md")));
+    }
+
+    let existing = paths.iter().find(|path| path.exists());
+    if let Some(existing) = existing.filter(|_| !overwrite) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", existing.display()),
+        ));
+    }
+    for (entry, path) in entries.iter().zip(&paths) {
+        std::fs::write(path, entry.markdown())?;
+    }
+    Ok(paths)
+}
+
+/// A title made safe to use as a file name, replacing path separators and characters that
+/// aren't allowed on common file systems.
+fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "Untitled".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestDir;
+    use chrono::Utc;
+
+    fn entry(id: usize, title: &str, markdown: &str) -> Entry {
+        let url = format!("memory:{id}");
+        Entry::new(url, title.into(), markdown.into(), None, Utc::now(), Utc::now(), None)
+    }
+
+    #[test]
+    fn export_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let out = dir.path().join("export");
+        let entries = vec![
+            entry(1, "Note", "First"),
+            entry(2, "note", "Second"),
+            entry(3, "Note", "Third"),
+            entry(4, "a/b: c?", "Unsafe"),
+            entry(5, "", "Untitled"),
+        ];
+
+        let paths = export(&entries, &out, false)?;
+
+        let names: Vec<_> = paths.iter().map(|path| path.strip_prefix(&out).unwrap()).collect();
+        assert_eq!(
+            vec!["Note.md", "note 2.md", "Note 3.md", "a-b- c-.md", "Untitled.md"],
+            names.iter().map(|name| name.to_str().unwrap()).collect::<Vec<_>>()
+        );
+        assert_eq!("Second", std::fs::read_to_string(&paths[1])?);
+        assert_eq!("Unsafe", std::fs::read_to_string(out.join("a-b- c-.md"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn export_existing_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        dir.write("Note.md", "---\ntitle: Note\n---\nOriginal")?;
+        let entries = vec![entry(1, "Other", "Other"), entry(2, "Note", "Copy")];
+
+        let error = export(&entries, dir.path(), false).unwrap_err();
+        assert_eq!(std::io::ErrorKind::AlreadyExists, error.kind());
+        assert!(std::fs::read_to_string(dir.path().join("Note.md"))?.ends_with("Original"));
+        assert!(!dir.path().join("Other.md").exists(), "nothing is written if any file exists");
+
+        export(&entries, dir.path(), true)?;
+        assert_eq!("Copy", std::fs::read_to_string(dir.path().join("Note.md"))?);
+        Ok(())
+    }
+}
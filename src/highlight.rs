@@ -0,0 +1,45 @@
+use crate::index::{SNIPPET_END, SNIPPET_START};
+
+const START: &str = "\x1b[1;33m";
+const END: &str = "\x1b[0m";
+
+/// Whether matched terms should be colored: only when asked for, writing to a terminal, and the
+/// user hasn't opted out of color with `NO_COLOR` (see https://no-color.org).
+pub fn enabled(requested: bool, is_terminal: bool) -> bool {
+    requested && is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Replaces the match markers in a search snippet with ANSI color codes, or removes them when
+/// `color` is false.
+pub fn highlight(snippet: &str, color: bool) -> String {
+    let mut output = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            SNIPPET_START if color => output.push_str(START),
+            SNIPPET_END if color => output.push_str(END),
+            SNIPPET_START | SNIPPET_END => {}
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_tests() {
+        let snippet = "apples and \u{2}pears\u{3}…";
+
+        assert_eq!("apples and \x1b[1;33mpears\x1b[0m…", highlight(snippet, true));
+        assert_eq!("apples and pears…", highlight(snippet, false));
+        assert_eq!("no matches", highlight("no matches", true));
+    }
+
+    #[test]
+    fn enabled_tests() {
+        assert!(!enabled(false, true), "disabled unless requested");
+        assert!(!enabled(true, false), "disabled when not writing to a terminal");
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::LAST_MODIFIED;
+use std::path::Path;
+use url::Url;
+
+use crate::markdown::{self, Collection, DialectKind, Document};
+
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    pub url: Url,
+    body: String,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl HttpSource {
+    pub fn fetch(url: Url) -> Result<Self, reqwest::Error> {
+        let response = reqwest::blocking::get(url.clone())?.error_for_status()?;
+        let modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|time| time.with_timezone(&Utc));
+        let body = response.text()?;
+        Ok(Self { url, body, modified })
+    }
+}
+
+impl markdown::Source for HttpSource {
+    fn read(&self) -> String {
+        self.body.clone()
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.url
+            .path_segments()
+            .and_then(|segments| segments.rev().find(|segment| !segment.is_empty()))
+            .and_then(|segment| Path::new(segment).file_stem())
+            .and_then(|stem| stem.to_str())
+    }
+
+    fn modified(&self) -> Option<DateTime<Utc>> {
+        self.modified
+    }
+}
+
+/// A collection of remote markdown documents. Each URL is fetched once when the collection is
+/// created, so network errors are reported up front rather than part way through a refresh.
+pub struct HttpCollection {
+    sources: Vec<HttpSource>,
+    dialect: DialectKind,
+}
+
+impl HttpCollection {
+    pub fn fetch(urls: &[Url], dialect: DialectKind) -> Result<Self, reqwest::Error> {
+        let sources =
+            urls.iter().map(|url| HttpSource::fetch(url.clone())).collect::<Result<_, _>>()?;
+        Ok(Self { sources, dialect })
+    }
+
+    /// The collection without downloading anything, for an index that won't be refreshed, so
+    /// its documents can still be found under its name.
+    pub fn unfetched(dialect: DialectKind) -> Self {
+        Self { sources: vec![], dialect }
+    }
+}
+
+impl Collection for HttpCollection {
+    fn name(&self) -> String {
+        "http".to_string()
+    }
+
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.sources
+            .iter()
+            .map(|source| Document {
+                source: Box::new(source.clone()),
+                dialect: self.dialect.dialect(),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::Source;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn serve(response: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        Url::parse(&format!("http://{address}/notes/remote-note.md")).unwrap()
+    }
+
+    #[test]
+    fn fetch_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let url = serve(concat!(
+            "HTTP/1.1 200 OK\r\n",
+            "Content-Length: 14\r\n",
+            "Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n",
+            "Connection: close\r\n",
+            "\r\n",
+            "# Remote note\n"
+        ));
+
+        let source = HttpSource::fetch(url.clone())?;
+
+        assert_eq!("# Remote note\n", source.read());
+        assert_eq!(url, source.url());
+        assert_eq!(Some("remote-note"), source.title());
+        assert_eq!(
+            Some(DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")?.with_timezone(&Utc)),
+            source.modified()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_error_tests() {
+        let not_found = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+        assert!(HttpSource::fetch(serve(not_found)).is_err(), "error status should be returned");
+        assert!(
+            HttpCollection::fetch(&[serve(not_found)], DialectKind::default()).is_err(),
+            "collection should return fetch errors"
+        );
+    }
+}
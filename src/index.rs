@@ -1,20 +1,266 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 
-use crate::markdown::collection::Collection;
+use crate::markdown::collection::{self, Collection, Directory};
+use crate::markdown::source::NamedSource;
+use crate::markdown::{DialectKind, Document};
+use crate::obsidian;
 
 use chrono::{DateTime, Utc};
-use indoc::indoc;
-use log::info;
-use rusqlite::{Connection, Transaction};
+use indoc::{formatdoc, indoc};
+use log::{info, warn};
+use rusqlite::types::{ToSqlOutput, Value};
+use rusqlite::{
+    params_from_iter, Connection, ErrorCode, OptionalExtension, ToSql, Transaction,
+    TransactionBehavior,
+};
+use schemars::JsonSchema;
 use serde::Serialize;
+use std::time::Duration;
+use url::Url;
+use walkdir::WalkDir;
 
 pub struct Index {
     pub connection: Connection,
     pub collections: Vec<Box<dyn crate::markdown::collection::Collection>>,
+    config: IndexConfig,
 }
 
-const SCHEMA_VERSION: i64 = 3;
+const SCHEMA_VERSION: i64 = 30;
+
+/// How long to wait for another process to release the database before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times to retry a write when the database is still busy after `BUSY_TIMEOUT`.
+const BUSY_RETRIES: u32 = 5;
+
+/// Words shorter than this say too little about a document to find others like it.
+const MIN_KEYWORD_LENGTH: usize = 4;
+
+/// How many of a document's most distinctive words are used to find similar documents.
+const SIMILAR_KEYWORDS: usize = 10;
+
+/// The FTS5 tokenizer used unless another is configured.
+pub const DEFAULT_TOKENIZER: &str = "porter unicode61 remove_diacritics 1 tokenchars '-#'";
+
+/// Options controlling how documents are indexed and searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexConfig {
+    /// FTS5 tokenizer definition for the word index. Changing it rebuilds the index.
+    pub tokenizer: String,
+    /// Rank documents with the query in their title ahead of those only matching in their text.
+    pub title_boost: bool,
+    /// Front matter keys, ignoring case, whose values are indexed with the text so searches find
+    /// them. Changing them rebuilds the index.
+    pub index_fields: Vec<String>,
+    /// Also index every three character sequence of the text, which substring searches need.
+    /// Changing it rebuilds the index.
+    pub substring_index: bool,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            tokenizer: DEFAULT_TOKENIZER.to_string(),
+            title_boost: true,
+            index_fields: vec![],
+            substring_index: false,
+        }
+    }
+}
+
+impl IndexConfig {
+    /// The indexed fields as they're recorded in the database, lowercased and sorted so listing
+    /// the same fields differently doesn't rebuild the index.
+    fn index_fields_key(&self) -> String {
+        let mut fields: Vec<String> =
+            self.index_fields.iter().map(|field| field.to_lowercase()).collect();
+        fields.sort();
+        fields.dedup();
+        fields.join(",")
+    }
+}
+
+/// Builds an [`Index`], for when the defaults of the `open` functions aren't enough.
+///
+/// ```ignore
+/// let index = IndexBuilder::new().in_memory().obsidian_vaults().title_boost(false).build()?;
+/// ```
+#[derive(Default)]
+pub struct IndexBuilder {
+    collections: Vec<Box<dyn Collection>>,
+    path: Option<PathBuf>,
+    dialect: DialectKind,
+    obsidian_vaults: bool,
+    directories: Vec<Directory>,
+    extensions: Vec<String>,
+    follow_links: bool,
+    open_vaults_only: bool,
+    auto_repair: bool,
+    index_fields: Option<Vec<String>>,
+    substring_index: Option<bool>,
+    config: IndexConfig,
+}
+
+#[allow(dead_code)]
+impl IndexBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a collection of documents to index. A plain `Path` or `PathBuf` is walked with the
+    /// builder's extensions and symlink setting, like a directory.
+    pub fn collection(mut self, collection: Box<dyn Collection>) -> Self {
+        self.collections.push(collection);
+        self
+    }
+
+    pub fn collections(mut self, collections: Vec<Box<dyn Collection>>) -> Self {
+        self.collections.extend(collections);
+        self
+    }
+
+    /// Adds a folder of documents, parsed with its own dialect. Its documents are found with the
+    /// builder's extensions, walking into symlinked folders if the builder does.
+    pub fn directory(mut self, directory: Directory) -> Self {
+        self.directories.push(directory);
+        self
+    }
+
+    /// Adds every vault known to Obsidian, parsed with the builder's dialect.
+    pub fn obsidian_vaults(mut self) -> Self {
+        self.obsidian_vaults = true;
+        self
+    }
+
+    /// Only index the Obsidian vaults currently open in Obsidian, leaving out closed ones.
+    pub fn open_vaults_only(mut self, open_vaults_only: bool) -> Self {
+        self.open_vaults_only = open_vaults_only;
+        self
+    }
+
+    /// Also index files with this extension in Obsidian vaults and directories, on top of the
+    /// defaults.
+    pub fn extension(mut self, extension: impl Into<String>) -> Self {
+        self.extensions.push(extension.into());
+        self
+    }
+
+    /// Walk into symlinked folders in Obsidian vaults and directories. Off by default.
+    pub fn follow_symlinks(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    pub fn dialect(mut self, dialect: DialectKind) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Stores the index in a database at `path`, creating its directory if needed.
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Whether a database at `path` that turns out to be corrupt is moved aside and rebuilt,
+    /// rather than failing. Off by default, as whatever is at `path` may not be an index at all.
+    pub fn auto_repair(mut self, auto_repair: bool) -> Self {
+        self.auto_repair = auto_repair;
+        self
+    }
+
+    /// Keeps the index in memory. This is the default.
+    pub fn in_memory(mut self) -> Self {
+        self.path = None;
+        self
+    }
+
+    pub fn tokenizer(mut self, tokenizer: impl Into<String>) -> Self {
+        self.config.tokenizer = tokenizer.into();
+        self
+    }
+
+    pub fn title_boost(mut self, title_boost: bool) -> Self {
+        self.config.title_boost = title_boost;
+        self
+    }
+
+    /// Also makes the values of this front matter key searchable as text. Unless any are given,
+    /// an existing database keeps the fields it was built with, and a new one indexes none. An
+    /// empty key indexes no fields without adding one.
+    pub fn index_field(mut self, key: impl Into<String>) -> Self {
+        let key = key.into();
+        let fields = self.index_fields.get_or_insert_with(Vec::new);
+        if !key.is_empty() {
+            fields.push(key);
+        }
+        self
+    }
+
+    /// Whether to build the index substring searches use, which takes around three times the
+    /// space of the word index. Unless given, an existing database keeps whatever it was built
+    /// with, and a new one is built without it.
+    pub fn substring_index(mut self, substring_index: bool) -> Self {
+        self.substring_index = Some(substring_index);
+        self
+    }
+
+    pub fn build(self) -> Result<Index, Box<dyn std::error::Error>> {
+        let mut collections = if self.obsidian_vaults {
+            obsidian::vaults(
+                self.dialect,
+                &self.extensions,
+                self.follow_links,
+                self.open_vaults_only,
+            )?
+        } else {
+            vec![]
+        };
+        let extensions = collection::extensions(&self.extensions);
+        let follow_links = self.follow_links;
+        let configured = |directory: Directory| -> Box<dyn Collection> {
+            let extensions = extensions.clone();
+            Box::new(Directory { extensions, follow_links, ..directory })
+        };
+        collections.extend(self.directories.into_iter().map(configured));
+        // Plain paths added as collections are walked like directories, with the same settings.
+        for collection in self.collections {
+            collections.push(match collection.directory() {
+                Some(directory) => configured(directory),
+                None => collection,
+            });
+        }
+
+        let connection = match &self.path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Index::open_database(path, self.auto_repair)?
+            }
+            None => Connection::open_in_memory()?,
+        };
+        // Settings that change what's indexed are kept from the database unless given, so that
+        // a run without them doesn't rebuild the index.
+        let built_with = Index::built_with(&connection).unwrap_or_default();
+        let config = IndexConfig {
+            index_fields: self.index_fields.unwrap_or(built_with.index_fields),
+            substring_index: self.substring_index.unwrap_or(built_with.substring_index),
+            ..self.config
+        };
+        Index::open_with_config(collections, connection, config)
+    }
+}
+
+/// Marks the start of a matched term in an entry's snippet.
+pub const SNIPPET_START: char = '\u{2}';
+/// Marks the end of a matched term in an entry's snippet.
+pub const SNIPPET_END: char = '\u{3}';
 
 #[allow(dead_code)]
 impl Index {
@@ -25,15 +271,61 @@ impl Index {
     pub fn ensure_schema_version(
         connection: &Connection,
     ) -> Result<bool, Box<dyn std::error::Error>> {
-        if Self::schema_version(connection) < SCHEMA_VERSION {
-            Self::create_schema(connection)?;
-            Ok(true)
-        } else {
-            Ok(false)
+        Self::ensure_schema(connection, &IndexConfig::default())
+    }
+
+    /// The config the database at `connection` was built with, if it has an index.
+    fn built_with(connection: &Connection) -> Option<IndexConfig> {
+        connection
+            .query_row("SELECT index_fields, substring_index FROM application", [], |row| {
+                let index_fields: String = row.get(0)?;
+                Ok(IndexConfig {
+                    index_fields: index_fields
+                        .split(',')
+                        .filter(|field| !field.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    substring_index: row.get(1)?,
+                    ..Default::default()
+                })
+            })
+            .ok()
+    }
+
+    /// Creates the schema if it is missing, out of date, or was built with a different tokenizer,
+    /// indexed fields or substring index.
+    fn ensure_schema(
+        connection: &Connection, config: &IndexConfig,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let current = || {
+            let built_with: Option<(String, String, bool)> = connection
+                .query_row(
+                    "SELECT tokenizer, index_fields, substring_index FROM application",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .ok();
+            let config =
+                (config.tokenizer.clone(), config.index_fields_key(), config.substring_index);
+            Self::schema_version(connection) >= SCHEMA_VERSION && built_with == Some(config)
+        };
+        if current() {
+            return Ok(false);
+        }
+
+        // Another process may be creating the schema too, so check again holding the write lock.
+        let tx = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        let created = !current();
+        if created {
+            Self::create_schema(&tx, config)?;
         }
+        tx.commit()?;
+        Ok(created)
     }
 
-    fn create_schema(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+    fn create_schema(
+        connection: &Connection, config: &IndexConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         info!("Creating database schema");
         connection.execute("DROP TABLE IF EXISTS documents", ())?;
         connection.execute(
@@ -41,24 +333,135 @@ impl Index {
             CREATE TABLE documents (
                 id INTEGER PRIMARY KEY,
                 uri TEXT NOT NULL UNIQUE,
+                collection TEXT,
+                path TEXT,
+                folder TEXT,
                 type TEXT,
                 title TEXT NOT NULL,
+                file_title TEXT,
                 markdown TEXT NOT NULL,
                 created TIMESTAMP NOT NULL,
                 modified TIMESTAMP NOT NULL,
+                published BOOLEAN,
+                length INTEGER NOT NULL,
+                checksum TEXT NOT NULL,
+                body_checksum TEXT NOT NULL,
                 last_seen_at TIMESTAMP NOT NULL
             )"},
             (),
         )?;
+        // Each of these indexes has to be kept up to date as documents are refreshed, which slows
+        // writes a little, but lets lookups, filters and sorts on them avoid scanning every row.
+        connection.execute("CREATE INDEX documents_title ON documents (title)", ())?;
+        // Types are matched ignoring case, which only an index with the same collation can help.
+        connection.execute("CREATE INDEX documents_type ON documents (type COLLATE NOCASE)", ())?;
+        connection.execute("CREATE INDEX documents_modified ON documents (modified)", ())?;
+        connection.execute("CREATE INDEX documents_created ON documents (created)", ())?;
 
         connection.execute("DROP TABLE IF EXISTS word_index", ())?;
         connection.execute(
-            indoc! {"
+            &formatdoc! {"
             CREATE VIRTUAL TABLE IF NOT EXISTS word_index USING fts5(
                 document_id UNINDEXED,
                 title,
                 text,
-                tokenize = \"porter unicode61 remove_diacritics 1 tokenchars '-#'\"
+                tokenize = \"{}\"
+            )", config.tokenizer.replace('"', "\"\"")},
+            (),
+        )?;
+
+        // Every three character sequence of the text is indexed, so words can be found from any
+        // part of them rather than just their start. This costs a lot of space, roughly three
+        // times that of the word index, in exchange for substring searches not having to scan
+        // every document, so it's only built when asked for.
+        connection.execute("DROP TABLE IF EXISTS text_trigrams", ())?;
+        if config.substring_index {
+            connection.execute(
+                indoc! {"
+                CREATE VIRTUAL TABLE text_trigrams USING fts5(
+                    document_id UNINDEXED,
+                    title,
+                    text,
+                    tokenize = \"trigram\"
+                )"},
+                (),
+            )?;
+        }
+
+        connection.execute("DROP TABLE IF EXISTS title_trigrams", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE VIRTUAL TABLE title_trigrams USING fts5(
+                document_id UNINDEXED,
+                title,
+                tokenize = \"trigram\"
+            )"},
+            (),
+        )?;
+
+        connection.execute("DROP TABLE IF EXISTS document_tags", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE TABLE document_tags (
+                document_id INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (document_id, tag)
+            )"},
+            (),
+        )?;
+
+        connection.execute("DROP TABLE IF EXISTS document_languages", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE TABLE document_languages (
+                document_id INTEGER NOT NULL,
+                language TEXT NOT NULL,
+                PRIMARY KEY (document_id, language)
+            )"},
+            (),
+        )?;
+
+        connection.execute("DROP TABLE IF EXISTS document_aliases", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE TABLE document_aliases (
+                document_id INTEGER NOT NULL,
+                alias TEXT NOT NULL,
+                PRIMARY KEY (document_id, alias)
+            )"},
+            (),
+        )?;
+        connection
+            .execute("CREATE INDEX document_aliases_alias ON document_aliases (alias)", ())?;
+
+        // Front matter values are all stored as text, so `priority: 2` is matched by "2".
+        connection.execute("DROP TABLE IF EXISTS document_metadata", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE TABLE document_metadata (
+                document_id INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (document_id, key, value)
+            )"},
+            (),
+        )?;
+        // Filters match keys and values ignoring case, so the index has to as well.
+        connection.execute(
+            "CREATE INDEX document_metadata_key_value ON document_metadata (key COLLATE NOCASE, value COLLATE NOCASE)",
+            (),
+        )?;
+
+        connection.execute("DROP TABLE IF EXISTS document_links", ())?;
+        connection.execute(
+            indoc! {"
+            CREATE TABLE document_links (
+                document_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                text TEXT NOT NULL,
+                page TEXT,
+                PRIMARY KEY (document_id, position)
             )"},
             (),
         )?;
@@ -68,7 +471,12 @@ impl Index {
             indoc! {"
                 CREATE TABLE application (
                     id INTEGER PRIMARY KEY,
-                    version INTEGER NOT NULL
+                    version INTEGER NOT NULL,
+                    tokenizer TEXT NOT NULL,
+                    index_fields TEXT NOT NULL,
+                    substring_index BOOLEAN NOT NULL,
+                    last_refresh TIMESTAMP,
+                    previous_refresh TIMESTAMP
                 )"
             },
             (),
@@ -76,9 +484,10 @@ impl Index {
 
         connection.execute(
             indoc! {"
-                INSERT INTO application (version) VALUES (?1)"
+                INSERT INTO application (version, tokenizer, index_fields, substring_index)
+                VALUES (?1, ?2, ?3, ?4)"
             },
-            [SCHEMA_VERSION],
+            (SCHEMA_VERSION, &config.tokenizer, config.index_fields_key(), config.substring_index),
         )?;
         Ok(())
     }
@@ -86,8 +495,19 @@ impl Index {
     pub fn open(
         collections: Vec<Box<dyn crate::markdown::collection::Collection>>, connection: Connection,
     ) -> Index {
-        Self::ensure_schema_version(&connection).expect("Failed to create database schema");
-        Index { connection, collections }
+        Self::open_with_config(collections, connection, IndexConfig::default())
+            .expect("Failed to create database schema")
+    }
+
+    pub fn open_with_config(
+        collections: Vec<Box<dyn crate::markdown::collection::Collection>>, connection: Connection,
+        config: IndexConfig,
+    ) -> Result<Index, Box<dyn std::error::Error>> {
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // Let searches read while another process refreshes. In-memory databases stay as they are.
+        connection.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        Self::ensure_schema(&connection, &config)?;
+        Ok(Index { connection, collections, config })
     }
 
     pub fn open_in_memory(
@@ -97,13 +517,79 @@ impl Index {
         Self::open(collections, connection)
     }
 
+    /// An in-memory index of `documents`, already refreshed, for trying out searches or testing
+    /// against without any files.
+    ///
+    /// ```
+    /// use markdown_db::index::Index;
+    /// use markdown_db::source::NamedSource;
+    ///
+    /// let index = Index::from_documents(vec![
+    ///     NamedSource::new("Shopping", "Eggs, flour and milk"),
+    ///     NamedSource::new("Pancakes", "Whisk the eggs into the flour"),
+    /// ])?;
+    /// assert_eq!(2, index.search("eggs")?.len());
+    /// assert_eq!("Pancakes", index.search("whisk")?.entries()[0].title());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_documents(documents: Vec<NamedSource>) -> Result<Index, rusqlite::Error> {
+        let mut index = Self::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+        Ok(index)
+    }
+
+    /// Opens the index in the database at `database_path`, failing rather than repairing it if
+    /// it's corrupt. Use `IndexBuilder::auto_repair` to have it moved aside and rebuilt.
     pub fn open_from_file(
         collections: Vec<Box<dyn crate::markdown::collection::Collection>>, database_path: &Path,
-    ) -> Index {
-        let connection = Connection::open(database_path).unwrap_or_else(|_| {
-            panic!("Failed to open database at {:?}", database_path.to_string_lossy())
-        });
-        Self::open(collections, connection)
+    ) -> Result<Index, Box<dyn std::error::Error>> {
+        let connection = Self::open_database(database_path, false)?;
+        Self::open_with_config(collections, connection, IndexConfig::default())
+    }
+
+    /// Opens the database at `path`, checking it can be read. If it can't because the file is
+    /// corrupt, such as after an interrupted write, it's moved aside to `<path>.corrupt-<time>`
+    /// and a new one created when `repair` is set, as everything in an index can be rebuilt from
+    /// the documents. Nothing is deleted, in case the file wasn't an index. Only the schema and
+    /// the settings read when opening the index are checked, since a full `PRAGMA
+    /// integrity_check` reads every page and would slow down every command on a large index, so
+    /// damage elsewhere is only found, as an error, by the query that reads it.
+    fn open_database(path: &Path, repair: bool) -> Result<Connection, Box<dyn std::error::Error>> {
+        let check = |connection: &Connection| {
+            connection.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+            // A new database has no settings yet, which isn't a problem.
+            match connection.query_row("SELECT * FROM application", [], |_| Ok(())) {
+                Err(error) if is_corrupt(&error) => Err(error),
+                _ => Ok(()),
+            }
+        };
+        let connection = Connection::open(path)?;
+        match check(&connection) {
+            Err(error) if repair && is_corrupt(&error) => {
+                let mut aside = path.as_os_str().to_owned();
+                aside.push(format!(".corrupt-{}", Utc::now().format("%Y%m%d%H%M%S")));
+                warn!(
+                    "Recreating the corrupt database at {}, moving it to {}: {error}",
+                    path.display(),
+                    aside.to_string_lossy()
+                );
+                drop(connection);
+                for suffix in ["", "-wal", "-shm"] {
+                    let (mut from, mut to) = (path.as_os_str().to_owned(), aside.clone());
+                    from.push(suffix);
+                    to.push(suffix);
+                    match std::fs::rename(&from, &to) {
+                        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(error.into());
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Connection::open(path)?)
+            }
+            Err(error) => Err(error.into()),
+            Ok(()) => Ok(connection),
+        }
     }
 
     pub fn path(&self) -> Option<String> {
@@ -114,87 +600,155 @@ impl Index {
         self.connection.query_row("SELECT COUNT(*) FROM documents", [], |row| row.get(0)).unwrap()
     }
 
+    /// The number of documents from each collection, in the order the collections were given.
+    pub fn size_by_collection(&self) -> Result<Vec<(String, i64)>, rusqlite::Error> {
+        let mut count = self
+            .connection
+            .prepare_cached("SELECT COUNT(*) FROM documents WHERE collection = ?1")?;
+        self.collections
+            .iter()
+            .map(|collection| {
+                let name = collection.name();
+                let size = count.query_row([&name], |row| row.get(0))?;
+                Ok((name, size))
+            })
+            .collect()
+    }
+
+    /// When the refresh before the most recent one started, so documents changed since then are
+    /// the ones the most recent refresh picked up. None until the index has been refreshed twice.
+    pub fn previous_refresh(&self) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+        self.connection.query_row("SELECT previous_refresh FROM application", [], |row| row.get(0))
+    }
+
     pub fn reset(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
-        Self::create_schema(&self.connection)?;
+        // Other connections never see the schema half rebuilt, with tables they use missing.
+        let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        Self::create_schema(&tx, &self.config)?;
+        tx.commit()?;
         Ok(true)
     }
 
+    pub fn reindex(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.reindex_changes(false)?;
+        Ok(())
+    }
+
+    /// Rebuilds the index from scratch, in one transaction so searches see either the old index
+    /// or the new one. A dry run rolls back, leaving the index as it was.
+    pub fn reindex_changes(
+        &mut self, dry_run: bool,
+    ) -> Result<Changes, Box<dyn std::error::Error>> {
+        let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        Self::create_schema(&tx, &self.config)?;
+        let changes = Self::refresh_(&tx, &self.collections, &self.config, Utc::now())?;
+        if dry_run {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
+        Ok(changes)
+    }
+
     pub fn refresh(&mut self) -> Result<(), rusqlite::Error> {
-        let tx = self.connection.transaction()?;
-        Self::refresh_(&tx, &self.collections)?;
-        tx.commit()?;
+        self.refresh_changes(false)?;
         Ok(())
     }
 
-    fn refresh_(
-        tx: &Transaction, collections: &Vec<Box<dyn Collection>>,
-    ) -> Result<(), rusqlite::Error> {
-        let timestamp = Utc::now();
+    /// Refreshes the index as though the time were `now`, which is recorded as when each document
+    /// was last seen and when the refresh ran. Documents last seen before `now` are removed.
+    pub fn refresh_at(&mut self, now: DateTime<Utc>) -> Result<Changes, rusqlite::Error> {
+        self.refresh_changes_at(false, now)
+    }
 
-        let mut update_unmodified_document = tx.prepare(indoc! {"
-            UPDATE documents SET last_seen_at = ?1 WHERE uri = ?2 AND modified >= ?3
-        "})?;
+    /// Refreshes the index, returning how many documents changed. A dry run goes through exactly
+    /// the same steps but rolls them back, to see what a refresh would do.
+    pub fn refresh_changes(&mut self, dry_run: bool) -> Result<Changes, rusqlite::Error> {
+        self.refresh_changes_at(dry_run, Utc::now())
+    }
 
-        let mut insert_into_documents = tx.prepare(indoc! {"
-            INSERT INTO documents (uri, title, type, markdown, created, modified, last_seen_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            ON CONFLICT(uri)
-            DO UPDATE SET uri = excluded.uri, last_seen_at = excluded.last_seen_at
+    fn refresh_changes_at(
+        &mut self, dry_run: bool, now: DateTime<Utc>,
+    ) -> Result<Changes, rusqlite::Error> {
+        with_retry(|| {
+            let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let changes = Self::refresh_(&tx, &self.collections, &self.config, now)?;
+            if dry_run {
+                tx.rollback()?;
+            } else {
+                tx.commit()?;
+            }
+            Ok(changes)
+        })
+    }
+
+    fn refresh_(
+        tx: &Transaction, collections: &Vec<Box<dyn Collection>>, config: &IndexConfig,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Changes, rusqlite::Error> {
+        let mut changes = Changes::default();
+
+        let mut select_document = tx.prepare(indoc! {"
+            SELECT 1 FROM documents WHERE uri = ?1
         "})?;
 
-        let mut delete_from_word_index = tx.prepare(indoc! {"
-            DELETE FROM word_index WHERE document_id = ?1
+        let mut update_unmodified_document = tx.prepare(indoc! {"
+            UPDATE documents SET last_seen_at = ?1 WHERE uri = ?2 AND modified >= ?3
         "})?;
 
-        let mut insert_into_word_index = tx.prepare(indoc! {"
-            INSERT INTO word_index (document_id, title, text) VALUES (?1, ?2, ?3)
+        let mut update_collection = tx.prepare(indoc! {"
+            UPDATE documents SET last_seen_at = ?1 WHERE collection = ?2
         "})?;
 
         for collection in collections {
-            for document in &collection.documents() {
-                if document.modified().is_none()
-                    || update_unmodified_document.execute((
+            let (name, root) = (collection.name(), collection.root());
+            // A collection that can't be listed, such as a vault whose folder has gone, keeps the
+            // documents it had rather than failing the refresh of every other collection.
+            let documents = match collection.documents_iter() {
+                Ok(documents) => documents,
+                Err(error) => {
+                    warn!("Skipping {name}: can't list its documents: {error}");
+                    update_collection.execute((&timestamp, &name))?;
+                    continue;
+                }
+            };
+            // Each document is dropped as soon as it's indexed, so only one is parsed at a time.
+            let mut listed = true;
+            for document in documents {
+                let document = match document {
+                    Ok(document) => document,
+                    Err(error) => {
+                        warn!("Couldn't list all of {name}: {error}");
+                        listed = false;
+                        continue;
+                    }
+                };
+                let document = &document.unparsed();
+                if document.modified().is_some()
+                    && update_unmodified_document.execute((
                         &timestamp,
                         &document.uri(),
                         &document.modified(),
-                    ))? != 1
+                    ))? == 1
                 {
-                    insert_into_documents.execute((
-                        &document.uri(),
-                        &document.title(),
-                        &document.doc_type(),
-                        &document.markdown(),
-                        &document.created(),
-                        &document.modified(),
-                        &timestamp,
-                    ))?;
-
-                    let id: u64 =
-                        tx.query_row("SELECT last_insert_rowid()", [], |row| row.get(0))?;
-
-                    delete_from_word_index.execute((id,))?;
-
-                    let tags = match document.front_matter() {
-                        Some(front_matter) => front_matter
-                            .tags()
-                            .map(|f| {
-                                f.iter()
-                                    .map(|tag| format!("#{tag}"))
-                                    .collect::<Vec<String>>()
-                                    .join(" ")
-                            })
-                            .unwrap_or("".to_string()),
-                        None => "".to_string(),
-                    };
-
-                    let text =
-                        format!("{} {} {}", &document.title().unwrap_or(""), document.text(), tags);
-                    info!("{}", text);
-
-                    insert_into_word_index.execute((id, document.title(), text))?;
+                    changes.unchanged += 1;
+                    continue;
+                }
+                let exists = select_document.exists([document.uri()])?;
+                let indexed =
+                    Self::index_or_skip(tx, &name, root.as_deref(), document, config, &timestamp)?;
+                if !indexed {
+                    changes.skipped += 1;
+                } else if exists {
+                    changes.updated += 1;
                 } else {
-                    //println!("Document {} is up to date", document.uri());
+                    changes.added += 1;
                 }
             }
+            // Documents that couldn't be listed may still exist, so are kept rather than removed.
+            if !listed {
+                update_collection.execute((&timestamp, &name))?;
+            }
         }
 
         info!("Deleting documents older than {}", timestamp);
@@ -202,271 +756,4165 @@ impl Index {
         let mut delete_from_documents = tx.prepare(indoc! {"
             DELETE FROM documents WHERE last_seen_at < ?1
         "})?;
-        delete_from_documents.execute([timestamp])?;
+        changes.removed = delete_from_documents.execute([timestamp])?;
 
         let mut delete_from_word_index = tx.prepare(indoc! {"
             DELETE FROM word_index WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = word_index.document_id)
         "})?;
         delete_from_word_index.execute([])?;
 
-        Ok(())
-    }
-
-    pub fn search(&self, query: &str) -> Result<SearchResults, Box<dyn std::error::Error>> {
-        info!("Searching for {}", query);
-
-        let parts: Vec<String> = query.split(' ').map(|part| format!("\"{part}\"*")).collect();
-
-        let mut match_word_index = self.connection.prepare(indoc! {"
-            SELECT uri, documents.title, markdown, type, created, modified, rank FROM documents
-            JOIN word_index ON word_index.document_id = documents.id
-            WHERE word_index MATCH ?1
+        let mut delete_from_title_trigrams = tx.prepare(indoc! {"
+            DELETE FROM title_trigrams WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = title_trigrams.document_id)
         "})?;
+        delete_from_title_trigrams.execute([])?;
 
-        fn build_entry(row: &rusqlite::Row) -> Result<Entry, rusqlite::Error> {
-            Ok(Entry::new(
-                row.get(0)?,
-                row.get(1)?,
-                row.get(2)?,
-                row.get(3)?,
-                row.get(4)?,
-                row.get(5)?,
-            ))
+        if config.substring_index {
+            let mut delete_from_text_trigrams = tx.prepare(indoc! {"
+                DELETE FROM text_trigrams WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = text_trigrams.document_id)
+            "})?;
+            delete_from_text_trigrams.execute([])?;
         }
 
-        let match_title = format!("{{title}} : {}", parts.join(" "));
-        let match_text = format!("{{text}} : {}", parts.join(" "));
+        let mut delete_from_document_tags = tx.prepare(indoc! {"
+            DELETE FROM document_tags WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = document_tags.document_id)
+        "})?;
+        delete_from_document_tags.execute([])?;
 
-        let title_rows = match_word_index.query_map([&match_title], build_entry)?;
-        let mut title_results: Vec<Entry> = title_rows.map(|row| row.unwrap()).collect();
+        let mut delete_from_document_languages = tx.prepare(indoc! {"
+            DELETE FROM document_languages WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = document_languages.document_id)
+        "})?;
+        delete_from_document_languages.execute([])?;
 
-        let text_rows = match_word_index.query_map([&match_text], build_entry)?;
-        let text_results: Vec<Entry> = text_rows.map(|row| row.unwrap()).collect();
+        let mut delete_from_document_aliases = tx.prepare(indoc! {"
+            DELETE FROM document_aliases WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = document_aliases.document_id)
+        "})?;
+        delete_from_document_aliases.execute([])?;
 
-        for result in text_results.into_iter() {
-            if !title_results.contains(&result) {
-                title_results.push(result);
-            }
-        }
+        let mut delete_from_document_links = tx.prepare(indoc! {"
+            DELETE FROM document_links WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = document_links.document_id)
+        "})?;
+        delete_from_document_links.execute([])?;
 
-        Ok(SearchResults { entries: title_results })
-    }
-}
+        let mut delete_from_document_metadata = tx.prepare(indoc! {"
+            DELETE FROM document_metadata WHERE NOT EXISTS (SELECT 1 FROM documents WHERE documents.id = document_metadata.document_id)
+        "})?;
+        delete_from_document_metadata.execute([])?;
 
-trait OtherToSql {
-    fn to_sql(&self) -> &str;
-}
+        tx.execute(
+            "UPDATE application SET previous_refresh = last_refresh, last_refresh = ?1",
+            [timestamp],
+        )?;
 
-impl OtherToSql for &PathBuf {
-    fn to_sql(&self) -> &str {
-        self.to_str().unwrap()
+        Ok(changes)
     }
-}
 
-impl<T: OtherToSql> OtherToSql for Option<T> {
-    fn to_sql(&self) -> &str {
-        match self {
-            Some(value) => value.to_sql(),
-            None => "",
+    /// Index `document` inside a savepoint, skipping it if it can't be read or parsing panics so
+    /// that a single pathological note can't abort a whole refresh. Returns false if the document
+    /// was skipped.
+    fn index_or_skip<'a>(
+        tx: &Transaction, collection: &str, root: Option<&Path>, document: &'a Document<'a>,
+        config: &IndexConfig, timestamp: &DateTime<Utc>,
+    ) -> Result<bool, rusqlite::Error> {
+        tx.execute_batch("SAVEPOINT index_document")?;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            document
+                .bytes()
+                .map(|_| Self::index_document(tx, collection, root, document, config, timestamp))
+        }));
+        match result {
+            Ok(Ok(result)) => {
+                result?;
+                tx.execute_batch("RELEASE index_document")?;
+                Ok(true)
+            }
+            Ok(Err(error)) => {
+                warn!("Skipping {}: {error}", document.uri());
+                tx.execute_batch("ROLLBACK TO index_document; RELEASE index_document")?;
+                Ok(false)
+            }
+            Err(_) => {
+                warn!("Skipping {}: panicked while indexing", document.uri());
+                tx.execute_batch("ROLLBACK TO index_document; RELEASE index_document")?;
+                Ok(false)
+            }
         }
     }
-}
-
-#[derive(Debug, Serialize, PartialEq, Eq)]
-pub struct Entry {
-    title: String,
-    url: String,
-    #[serde(rename = "type")]
-    doc_type: Option<String>,
-    markdown: String,
-    created: DateTime<Utc>,
-    modified: DateTime<Utc>,
-}
 
-impl Entry {
-    pub fn new(
-        url: String, title: String, markdown: String, doc_type: Option<String>,
-        created: DateTime<Utc>, modified: DateTime<Utc>,
-    ) -> Entry {
-        Entry { title, url, doc_type, markdown, created, modified }
+    /// Remove the document with `uri` from the index without refreshing anything else. Returns
+    /// false if there was no such document.
+    pub fn remove(&mut self, uri: &str) -> Result<bool, rusqlite::Error> {
+        with_retry(|| {
+            let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let removed = Self::remove_document(&tx, uri, &self.config)?;
+            tx.commit()?;
+            Ok(removed)
+        })
     }
 
-    pub fn uri(&self) -> &str {
-        &self.url
+    /// The uri of the document at `path` in one of the index's collections, whether or not the
+    /// file still exists.
+    pub fn uri_for(&self, path: &Path) -> Option<Url> {
+        self.collections.iter().find_map(|collection| Some(collection.document(path)?.uri()))
     }
-}
 
-impl Display for Entry {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.uri())
+    /// Index the file at `path`, leaving every other document as it is, and return its id. The
+    /// file must belong to one of the index's collections, which decides how it's parsed, as
+    /// otherwise the next refresh would remove it again.
+    pub fn add_path(&mut self, path: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+        if !path.is_file() {
+            return Err(format!("No file at {}", path.display()).into());
+        }
+        let (collection, document) = self
+            .collections
+            .iter()
+            .find_map(|collection| Some((collection, collection.document(path)?)))
+            .ok_or_else(|| format!("{} isn't in any collection", path.display()))?;
+        let (name, root) = (collection.name(), collection.root());
+        let id = with_retry(|| {
+            let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let config = &self.config;
+            let id =
+                Self::index_document(&tx, &name, root.as_deref(), &document, config, &Utc::now())?;
+            tx.commit()?;
+            Ok(id)
+        })?;
+        Ok(id)
     }
-}
-
-pub struct SearchResults {
-    entries: Vec<Entry>,
-}
 
-impl SearchResults {
-    pub fn entries(&self) -> &Vec<Entry> {
-        &self.entries
+    /// Re-index the single file at `path`, removing it from the index if it no longer exists.
+    /// Returns false if the path doesn't belong to any of the index's collections.
+    pub fn refresh_path(&mut self, path: &Path) -> Result<bool, rusqlite::Error> {
+        let document = self
+            .collections
+            .iter()
+            .find_map(|collection| Some((collection, collection.document(path)?)));
+        with_retry(|| {
+            let tx = self.connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let indexed = match &document {
+                Some((collection, document)) if path.exists() => {
+                    let root = collection.root();
+                    Self::index_or_skip(
+                        &tx,
+                        &collection.name(),
+                        root.as_deref(),
+                        document,
+                        &self.config,
+                        &Utc::now(),
+                    )?;
+                    true
+                }
+                Some((_, document)) => {
+                    Self::remove_document(&tx, document.uri().as_str(), &self.config)?;
+                    true
+                }
+                None => false,
+            };
+            tx.commit()?;
+            Ok(indexed)
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn len(&self) -> usize {
-        self.entries.len()
+    /// The directories of every collection in the index.
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.collections.iter().filter_map(|collection| collection.root()).collect()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use url::Url;
+    /// Upserts `document` and everything indexed for it, returning its id.
+    fn index_document<'a>(
+        tx: &Transaction, collection: &str, root: Option<&Path>, document: &'a Document<'a>,
+        config: &IndexConfig, timestamp: &DateTime<Utc>,
+    ) -> Result<u64, rusqlite::Error> {
+        let mut insert_into_documents = tx.prepare_cached(indoc! {"
+            INSERT INTO documents (uri, collection, path, folder, title, file_title, type, markdown, created, modified, published, length, checksum, body_checksum, last_seen_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, coalesce(?8, ''), ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+            ON CONFLICT(uri)
+            DO UPDATE SET collection = excluded.collection, path = excluded.path, folder = excluded.folder, title = excluded.title, file_title = excluded.file_title, type = excluded.type, markdown = coalesce(?8, markdown),
+                created = excluded.created, modified = excluded.modified, published = excluded.published, length = excluded.length, checksum = excluded.checksum, body_checksum = excluded.body_checksum, last_seen_at = excluded.last_seen_at
+            RETURNING id
+        "})?;
 
-    use crate::test::TestDir;
+        // The indexed text of the document as it was, if only its front matter has changed since.
+        let mut select_unchanged_text = tx.prepare_cached(indoc! {"
+            SELECT documents.title, text
+            FROM documents
+            JOIN word_index ON word_index.document_id = documents.id
+            WHERE uri = ?1 AND body_checksum = ?2
+        "})?;
 
-    #[test]
-    fn ensure_schema_tests() -> Result<(), Box<dyn std::error::Error>> {
-        let connection = Connection::open_in_memory()?;
-        assert_eq!(true, Index::ensure_schema_version(&connection).unwrap());
-        assert_eq!(false, Index::ensure_schema_version(&connection).unwrap());
-        Ok(())
-    }
+        let mut delete_from_word_index = tx.prepare_cached(indoc! {"
+            DELETE FROM word_index WHERE document_id = ?1
+        "})?;
 
-    #[test]
-    fn refresh_index_tests() -> Result<(), Box<dyn std::error::Error>> {
-        let dir = TestDir::new();
-        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        let mut insert_into_word_index = tx.prepare_cached(indoc! {"
+            INSERT INTO word_index (document_id, title, text) VALUES (?1, ?2, ?3)
+        "})?;
 
-        assert_eq!(0, index.size());
+        let mut delete_from_title_trigrams = tx.prepare_cached(indoc! {"
+            DELETE FROM title_trigrams WHERE document_id = ?1
+        "})?;
 
-        dir.write("document.md", "Initial document")?;
-        index.refresh()?;
+        let mut insert_into_title_trigrams = tx.prepare_cached(indoc! {"
+            INSERT INTO title_trigrams (document_id, title) VALUES (?1, ?2)
+        "})?;
+
+        let mut delete_from_document_tags = tx.prepare_cached(indoc! {"
+            DELETE FROM document_tags WHERE document_id = ?1
+        "})?;
+
+        let mut insert_into_document_tags = tx.prepare_cached(indoc! {"
+            INSERT OR IGNORE INTO document_tags (document_id, tag) VALUES (?1, ?2)
+        "})?;
+
+        let mut delete_from_document_languages = tx.prepare_cached(indoc! {"
+            DELETE FROM document_languages WHERE document_id = ?1
+        "})?;
+
+        let mut insert_into_document_languages = tx.prepare_cached(indoc! {"
+            INSERT OR IGNORE INTO document_languages (document_id, language) VALUES (?1, ?2)
+        "})?;
+
+        let mut delete_from_document_aliases = tx.prepare_cached(indoc! {"
+            DELETE FROM document_aliases WHERE document_id = ?1
+        "})?;
+
+        let mut insert_into_document_aliases = tx.prepare_cached(indoc! {"
+            INSERT OR IGNORE INTO document_aliases (document_id, alias) VALUES (?1, ?2)
+        "})?;
+
+        let mut delete_from_document_metadata = tx.prepare_cached(indoc! {"
+            DELETE FROM document_metadata WHERE document_id = ?1
+        "})?;
+
+        let mut insert_into_document_metadata = tx.prepare_cached(indoc! {"
+            INSERT OR IGNORE INTO document_metadata (document_id, key, value) VALUES (?1, ?2, ?3)
+        "})?;
+
+        let mut delete_from_document_links = tx.prepare_cached(indoc! {"
+            DELETE FROM document_links WHERE document_id = ?1
+        "})?;
+
+        let mut insert_into_document_links = tx.prepare_cached(indoc! {"
+            INSERT INTO document_links (document_id, position, url, text, page) VALUES (?1, ?2, ?3, ?4, ?5)
+        "})?;
+
+        // When only the front matter has changed, the text of the body is taken from the index
+        // rather than extracted again, and its links and code languages are left as they are.
+        // The text starts with the title, and can't be reused if front matter values follow it.
+        let body_checksum = document.body_checksum();
+        let index_fields = &config.index_fields;
+        let unchanged_text: Option<String> = if index_fields.is_empty() {
+            select_unchanged_text
+                .query_row((document.uri(), &body_checksum), |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .optional()?
+                .and_then(|(title, text)| Some(text.strip_prefix(&format!("{title} "))?.into()))
+        } else {
+            None
+        };
+        let body_changed = unchanged_text.is_none();
+
+        // Sources without their own metadata, like plain strings, are untitled and dated when
+        // they are indexed.
+        let path = document.path();
+        let id: u64 = insert_into_documents.query_row(
+            (
+                &document.uri(),
+                collection,
+                &path.as_ref().map(|path| path.to_string_lossy().into_owned()),
+                &path.as_deref().zip(root).and_then(|(path, root)| folder(path, root)),
+                &document.title().unwrap_or(""),
+                &document.file_title(),
+                &document.doc_type(),
+                &body_changed.then(|| document.markdown()),
+                &document.created().unwrap_or(*timestamp),
+                &document.modified().unwrap_or(*timestamp),
+                &document.published(),
+                &document.len(),
+                &document.checksum(),
+                &body_checksum,
+                timestamp,
+            ),
+            |row| row.get(0),
+        )?;
+
+        delete_from_word_index.execute((id,))?;
+
+        let body = unchanged_text.as_deref().unwrap_or_else(|| document.text());
+        let mut text = format!("{} {}", document.title().unwrap_or(""), body);
+        for (key, value) in document.metadata() {
+            if index_fields.iter().any(|field| field.eq_ignore_ascii_case(&key)) {
+                text.push(' ');
+                text.push_str(&value);
+            }
+        }
+        info!("{}", text);
+
+        insert_into_word_index.execute((id, document.title(), &text))?;
+
+        if config.substring_index {
+            let mut delete_from_text_trigrams = tx.prepare_cached(indoc! {"
+                DELETE FROM text_trigrams WHERE document_id = ?1
+            "})?;
+            delete_from_text_trigrams.execute((id,))?;
+            let mut insert_into_text_trigrams = tx.prepare_cached(indoc! {"
+                INSERT INTO text_trigrams (document_id, title, text) VALUES (?1, ?2, ?3)
+            "})?;
+            insert_into_text_trigrams.execute((id, document.title(), &text))?;
+        }
+
+        delete_from_title_trigrams.execute((id,))?;
+        insert_into_title_trigrams.execute((id, document.title()))?;
+
+        delete_from_document_tags.execute((id,))?;
+        for tag in document.tags() {
+            for tag in tag_hierarchy(&tag.to_lowercase()) {
+                insert_into_document_tags.execute((id, tag))?;
+            }
+        }
+
+        if body_changed {
+            delete_from_document_languages.execute((id,))?;
+            for language in document.code_languages() {
+                insert_into_document_languages.execute((id, language))?;
+            }
+        }
+
+        delete_from_document_aliases.execute((id,))?;
+        for alias in document.aliases() {
+            insert_into_document_aliases.execute((id, alias))?;
+        }
+
+        delete_from_document_metadata.execute((id,))?;
+        for (key, value) in document.metadata() {
+            insert_into_document_metadata.execute((id, key, value))?;
+        }
+
+        if body_changed {
+            delete_from_document_links.execute((id,))?;
+            for (position, link) in document.links().iter().enumerate() {
+                insert_into_document_links.execute((
+                    id,
+                    position,
+                    link.url(),
+                    link.text(),
+                    link.page(),
+                ))?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Deletes the document with `uri` along with everything indexed for it, returning whether
+    /// there was one.
+    fn remove_document(
+        tx: &Transaction, uri: &str, config: &IndexConfig,
+    ) -> Result<bool, rusqlite::Error> {
+        tx.execute(
+            "DELETE FROM word_index WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        if config.substring_index {
+            tx.execute(
+                "DELETE FROM text_trigrams WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+                [uri],
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM title_trigrams WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        tx.execute(
+            "DELETE FROM document_tags WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        tx.execute(
+            "DELETE FROM document_languages WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        tx.execute(
+            "DELETE FROM document_aliases WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        tx.execute(
+            "DELETE FROM document_metadata WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        tx.execute(
+            "DELETE FROM document_links WHERE document_id IN (SELECT id FROM documents WHERE uri = ?1)",
+            [uri],
+        )?;
+        Ok(tx.execute("DELETE FROM documents WHERE uri = ?1", [uri])? > 0)
+    }
+
+    pub fn search(&self, query: &str) -> Result<SearchResults, Box<dyn std::error::Error>> {
+        self.search_with(query, &SearchOptions::default())
+    }
+
+    /// The document titled exactly `title`, matching case, without going through the full-text
+    /// index. Failing that, the document with `title` as one of its aliases. If several match,
+    /// the first indexed is returned. Only documents passing the filters in `options` are looked
+    /// at, and the document's links are added if `options` asks for them.
+    pub fn find_by_title(
+        &self, title: &str, options: &SearchOptions,
+    ) -> Result<Option<Entry>, Box<dyn std::error::Error>> {
+        let plan = self.plan("", options)?;
+        let columns = &plan.columns;
+        let filter = plan.filter();
+        let params = std::iter::once(&title as &dyn ToSql).chain(plan.filter_params());
+        let entry = self
+            .connection
+            .query_row(
+                &formatdoc! {"
+                    SELECT {columns}, NULL, NULL
+                    FROM documents
+                    WHERE (
+                        documents.title = ?1
+                        OR documents.id IN (SELECT document_id FROM document_aliases WHERE alias = ?1)
+                    ) {filter}
+                    ORDER BY documents.title = ?1 DESC, documents.id
+                    LIMIT 1
+                "},
+                params_from_iter(params),
+                Entry::from_row,
+            )
+            .optional()?;
+        let mut entries: Vec<Entry> = entry.into_iter().collect();
+        if options.with_links {
+            self.add_links(&mut entries)?;
+        }
+        Ok(entries.pop())
+    }
+
+    /// The document with this uri, without searching.
+    pub fn find_by_uri(&self, uri: &str) -> Result<Option<Entry>, rusqlite::Error> {
+        self.find_where("uri = ?", &uri)
+    }
+
+    /// The document with this id, as given by `Entry::id`, without searching.
+    pub fn find_by_id(&self, id: i64) -> Result<Option<Entry>, rusqlite::Error> {
+        self.find_where("id = ?", &id)
+    }
+
+    fn find_where(
+        &self, condition: &str, param: &dyn ToSql,
+    ) -> Result<Option<Entry>, rusqlite::Error> {
+        self.connection
+            .query_row(
+                &formatdoc! {"
+                    SELECT uri, title, markdown, type, created, modified, path, file_title, id, NULL, NULL, checksum
+                    FROM documents
+                    WHERE {condition}
+                "},
+                [param],
+                |row| Ok(Entry { checksum: row.get(11)?, ..Entry::from_row(row)? }),
+            )
+            .optional()
+    }
+
+    /// Works out the MATCH expressions and filters for a search, without running it.
+    fn plan<'q>(
+        &self, query: &'q str, options: &SearchOptions,
+    ) -> Result<Plan<'q>, Box<dyn std::error::Error>> {
+        let (mut tags, mut types, mut titles, mut nears, mut words) =
+            (vec![], vec![], vec![], vec![], vec![]);
+        let terms = if options.fixed_string {
+            Some(query.trim()).filter(|query| !query.is_empty()).into_iter().collect()
+        } else {
+            query_terms(query)
+        };
+        for term in terms {
+            match qualifier(term) {
+                _ if options.fixed_string => words.push(term),
+                Some(("tag", tag)) => tags.push(tag),
+                Some(("type", doc_type)) => types.push(doc_type),
+                Some(("title", title)) => titles.push(title),
+                _ if term.len() > 1 && term.starts_with('#') => tags.push(term),
+                _ if near_query(term).is_some() => nears.push(term),
+                _ => words.push(term),
+            }
+        }
+        // A query of only short words matches nothing, rather than every document.
+        let searched = !words.is_empty();
+        words.retain(|word| word.chars().count() >= options.min_term_length);
+        let matches_nothing = searched && words.is_empty() && nears.is_empty();
+        if options.substring && !nears.is_empty() {
+            return Err("near: groups can't be used with a substring search".into());
+        }
+        if options.substring && !self.config.substring_index {
+            return Err(
+                "Substring search needs the substring index, built with --substring-index".into()
+            );
+        }
+        // The trigram index matches a phrase anywhere within words, so there's no need for a
+        // prefix query there.
+        let (table, mut parts): (_, Vec<String>) = if options.substring {
+            ("text_trigrams", words.iter().map(|part| phrase(part)).collect())
+        } else if options.exact || options.fixed_string {
+            ("word_index", words.iter().map(|part| phrase(part)).collect())
+        } else {
+            ("word_index", words.iter().map(|part| format!("{}*", phrase(part))).collect())
+        };
+        parts.extend(nears.iter().filter_map(|near| near_query(near)));
+
+        let mut filters: Vec<(&str, Vec<Box<dyn ToSql>>)> = vec![];
+
+        // Tags are stored lowercase along with all their parents, so a parent tag also matches
+        // documents tagged with any of its children.
+        for tag in tags {
+            filters.push((
+                "AND documents.id IN (SELECT document_id FROM document_tags WHERE tag = ?)\n",
+                vec![Box::new(tag.trim_start_matches('#').trim_end_matches('/').to_lowercase())],
+            ));
+        }
+
+        for doc_type in types {
+            filters.push(("AND type = ? COLLATE NOCASE\n", vec![Box::new(doc_type.to_string())]));
+        }
+
+        // Title words are matched against the word index whatever the search, so they can be
+        // combined with a substring search of the text.
+        for title in titles {
+            let star = if options.exact { "" } else { "*" };
+            filters.push((
+                "AND documents.id IN (SELECT document_id FROM word_index WHERE word_index MATCH ?)\n",
+                vec![Box::new(format!("{{title}} : {}{star}", phrase(title)))],
+            ));
+        }
+
+        // Vaults are chosen by name, or by id, which is shown with the name when it's shared.
+        if let Some(vault) = &options.vault {
+            let chosen = self.collections.iter().find(|collection| {
+                collection.name() == *vault || collection.id().as_ref() == Some(vault)
+            });
+            let Some(chosen) = chosen else {
+                let names: Vec<String> =
+                    self.collections.iter().map(|collection| collection.name()).collect();
+                return Err(format!(
+                    "Unknown vault `{vault}`, expected one of: {}",
+                    names.join(", ")
+                )
+                .into());
+            };
+            filters.push(("AND collection = ?\n", vec![Box::new(chosen.name())]));
+        }
+
+        if let Some(language) = &options.language {
+            filters.push((
+                "AND documents.id IN (SELECT document_id FROM document_languages WHERE language = ?)\n",
+                vec![Box::new(language.to_lowercase())],
+            ));
+        }
+
+        if let Some(folder) = &options.folder {
+            let folder = folder.trim_matches('/');
+            if !folder.is_empty() {
+                filters.push(("AND instr(folder, ?) = 1\n", vec![Box::new(format!("{folder}/"))]));
+            }
+        }
+
+        for (key, value) in &options.metadata {
+            filters.push((
+                "AND documents.id IN (SELECT document_id FROM document_metadata WHERE key = ? COLLATE NOCASE AND value = ? COLLATE NOCASE)\n",
+                vec![Box::new(key.clone()), Box::new(value.clone())],
+            ));
+        }
+
+        if let Some(doc_type) = &options.doc_type {
+            filters.push(("AND type = ? COLLATE NOCASE\n", vec![Box::new(doc_type.clone())]));
+        }
+
+        for tag in &options.exclude_tags {
+            filters.push((
+                "AND documents.id NOT IN (SELECT document_id FROM document_tags WHERE tag = ?)\n",
+                vec![Box::new(tag.trim_start_matches('#').trim_end_matches('/').to_lowercase())],
+            ));
+        }
+
+        // Documents without a type are never excluded by type.
+        for doc_type in &options.exclude_types {
+            filters.push((
+                "AND (type IS NULL OR type != ? COLLATE NOCASE)\n",
+                vec![Box::new(doc_type.clone())],
+            ));
+        }
+
+        if let Some(modified_since) = options.modified_since {
+            filters.push(("AND modified >= ?\n", vec![Box::new(modified_since)]));
+        }
+
+        if let Some(created_since) = options.created_since {
+            filters.push(("AND created >= ?\n", vec![Box::new(created_since)]));
+        }
+
+        if options.published_only {
+            filters.push((
+                "AND coalesce(published, ?) = 1\n",
+                vec![Box::new(!options.unmarked_as_drafts)],
+            ));
+        }
+
+        if let Some(changed_since) = options.changed_since {
+            filters.push(("AND max(created, modified) > ?\n", vec![Box::new(changed_since)]));
+        }
+
+        // Markdown is by far the largest column, so it's left out unless it will be used.
+        let markdown = match &options.fields {
+            Some(fields) if !fields.contains(&Field::Markdown) => "''",
+            _ => "markdown",
+        };
+        let columns =
+            format!("uri, documents.title, {markdown}, type, created, modified, path, file_title, documents.id");
+
+        let match_any = options.match_any;
+        Ok(Plan { words, parts, table, filters, columns, matches_nothing, match_any })
+    }
+
+    /// A description of how `query` would be searched: the MATCH expression for each pass and
+    /// the filters applied, with their values.
+    pub fn explain(
+        &self, query: &str, options: &SearchOptions,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let plan = self.plan(query, options)?;
+        let mut explanation = format!("Query: {query}\n");
+        if plan.matches_nothing {
+            let length = options.min_term_length;
+            explanation
+                .push_str(&format!("Every word is shorter than {length}, nothing matches\n"));
+        } else if plan.parts.is_empty() {
+            explanation.push_str("No MATCH, all documents are filtered\n");
+        } else {
+            if self.config.title_boost || options.title_only {
+                explanation.push_str(&format!("Title MATCH: {}\n", plan.match_title()));
+            }
+            if !options.title_only {
+                explanation.push_str(&format!("Text MATCH: {}\n", plan.match_text()));
+            }
+            if options.substring {
+                explanation.push_str("Substring: matched within words using the trigram index\n");
+            }
+            if options.fuzzy {
+                explanation.push_str("Fuzzy: titles close to the words when nothing matches\n");
+            }
+        }
+        for (clause, params) in &plan.filters {
+            let values: Vec<String> = params.iter().map(|param| describe(param.as_ref())).collect();
+            explanation.push_str(&format!("Filter: {} -- {}\n", clause.trim(), values.join(", ")));
+        }
+        if let Some(min_score) = options.min_score {
+            explanation.push_str(&format!("Min score: {min_score}\n"));
+        }
+        if let Some(limit) = options.limit {
+            explanation.push_str(&format!("Limit: {limit}\n"));
+        }
+        Ok(explanation)
+    }
+
+    /// Searches for `query`, retrying once if another process changed the schema part way
+    /// through, such as by resetting the index.
+    pub fn search_with(
+        &self, query: &str, options: &SearchOptions,
+    ) -> Result<SearchResults, Box<dyn std::error::Error>> {
+        let mut entries = vec![];
+        self.search_each(query, options, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        Ok(SearchResults { entries })
+    }
+
+    /// Searches for `query` like `search_with`, but hands each entry to `each` as it's read rather
+    /// than collecting them all first, returning how many there were. Only title matches are
+    /// gathered up front, as they're ranked above the rest. Returning an error from `each` stops
+    /// the search, and a schema change is only retried if `each` hasn't been called yet.
+    pub fn search_each(
+        &self, query: &str, options: &SearchOptions,
+        mut each: impl FnMut(Entry) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        // Once an entry has been handed out, searching again would hand it out twice.
+        let emitted = Cell::new(false);
+        let mut each = |entry| {
+            emitted.set(true);
+            each(entry)
+        };
+        with_reprepare(
+            &self.connection,
+            || !emitted.get(),
+            || self.search_(query, options, &mut each),
+        )
+    }
+
+    /// Writes each result of searching for `query` to `out` as soon as it's found, as a line of
+    /// compact JSON with the chosen `fields`, returning how many there were.
+    pub fn search_ndjson(
+        &self, query: &str, options: &SearchOptions, fields: &[Field], out: &mut dyn Write,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        self.search_each(query, options, |entry| {
+            serde_json::to_writer(&mut *out, &Projection { entry: &entry, fields })?;
+            writeln!(out)?;
+            Ok(())
+        })
+    }
+
+    fn search_(
+        &self, query: &str, options: &SearchOptions,
+        each: &mut dyn FnMut(Entry) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        info!("Searching for {}", query);
+
+        let plan = self.plan(query, options)?;
+        let (words, parts, table, columns) = (&plan.words, &plan.parts, plan.table, &plan.columns);
+        let filter = plan.filter();
+        let filter_params = plan.filter_params();
+        let limit = options.limit.unwrap_or(usize::MAX);
+
+        // Hands on an entry if it scores highly enough, adding its links if they were asked for,
+        // and returns whether there's room for more.
+        let mut count = 0;
+        let mut emit = |mut entry: Entry| -> Result<bool, Box<dyn std::error::Error>> {
+            let min_score = options.min_score.unwrap_or(f64::MIN);
+            if count < limit && entry.score.is_none_or(|score| score >= min_score) {
+                if options.with_links {
+                    self.add_links(std::slice::from_mut(&mut entry))?;
+                }
+                count += 1;
+                each(entry)?;
+            }
+            Ok(count < limit)
+        };
+
+        if plan.matches_nothing {
+            return Ok(0);
+        }
+
+        if parts.is_empty() {
+            // With nothing to rank by, such as a search for only a tag, the most recently modified
+            // documents come first.
+            let mut match_documents = self.connection.prepare(&formatdoc! {"
+                SELECT {columns}, NULL, NULL
+                FROM documents
+                WHERE 1 {filter}
+                ORDER BY modified DESC, uri
+            "})?;
+            let mut rows = match_documents.query(params_from_iter(&filter_params))?;
+            while let Some(row) = rows.next()? {
+                if !emit(Entry::from_row(row)?)? {
+                    break;
+                }
+            }
+            return Ok(count);
+        }
+
+        // Each pass is ordered by relevance. Documents can rank the same, such as several titled
+        // "Index", so ties fall back to the most recently modified and then the uri, keeping the
+        // order stable between searches.
+        let order = "ORDER BY rank, modified DESC, uri";
+        let mut match_word_index = self.connection.prepare(&formatdoc! {"
+            SELECT {columns},
+                snippet({table}, 2, '{SNIPPET_START}', '{SNIPPET_END}', '…', 12), rank
+            FROM documents
+            JOIN {table} ON {table}.document_id = documents.id
+            WHERE {table} MATCH ? {filter}
+            {order}
+        "})?;
+
+        let match_title = plan.match_title();
+        let match_text = plan.match_text();
+
+        let mut title_results: Vec<Entry> = vec![];
+        if self.config.title_boost || options.title_only {
+            let title_params =
+                std::iter::once(&match_title as &dyn ToSql).chain(filter_params.iter().copied());
+            let title_rows =
+                match_word_index.query_map(params_from_iter(title_params), Entry::from_row)?;
+            title_results = title_rows.collect::<Result<_, _>>()?;
+        }
+
+        // Documents matching on both title and text keep their title ranking, but take the text
+        // snippet as that's the one with the matched terms marked.
+        let title_urls: HashSet<String> =
+            title_results.iter().map(|entry| entry.url.clone()).collect();
+        if !options.title_only && !title_results.is_empty() {
+            let mut select_snippets = self.connection.prepare(&formatdoc! {"
+                SELECT uri, snippet({table}, 2, '{SNIPPET_START}', '{SNIPPET_END}', '…', 12)
+                FROM documents
+                JOIN {table} ON {table}.document_id = documents.id
+                WHERE {table} MATCH ?1 AND uri IN (SELECT value FROM json_each(?2))
+            "})?;
+            let urls = serde_json::to_string(&title_urls)?;
+            let snippets = select_snippets.query_map((&match_text, urls), |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?))
+            })?;
+            let mut snippets: HashMap<String, String> = snippets.collect::<Result<_, _>>()?;
+            for entry in &mut title_results {
+                if let Some(snippet) = snippets.remove(&entry.url) {
+                    entry.snippet = Some(snippet);
+                }
+            }
+        }
+        let mut matched = !title_results.is_empty();
+        let mut more = true;
+        for entry in title_results {
+            more = emit(entry)?;
+            if !more {
+                break;
+            }
+        }
+
+        if more && !options.title_only {
+            let text_params =
+                std::iter::once(&match_text as &dyn ToSql).chain(filter_params.iter().copied());
+            let mut rows = match_word_index.query(params_from_iter(text_params))?;
+            while let Some(row) = rows.next()? {
+                let entry = Entry::from_row(row)?;
+                if !title_urls.contains(&entry.url) {
+                    matched = true;
+                    if !emit(entry)? {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !matched && options.fuzzy {
+            for entry in self.fuzzy_title_matches(words, columns, &filter, &filter_params)? {
+                if !emit(entry)? {
+                    break;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Documents with a title word close to every word of the query, ordered by their total edit
+    /// distance. Candidates come from the trigram index of titles, so only words of three or more
+    /// characters can match.
+    fn fuzzy_title_matches(
+        &self, words: &[&str], columns: &str, filter: &str, filter_params: &[&dyn ToSql],
+    ) -> Result<Vec<Entry>, rusqlite::Error> {
+        let words: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+        let trigrams: Vec<String> = words
+            .iter()
+            .flat_map(|word| trigrams(word))
+            .map(|trigram| format!("\"{}\"", trigram.replace('"', "\"\"")))
+            .collect();
+        if trigrams.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut match_title_trigrams = self.connection.prepare(&formatdoc! {"
+            SELECT {columns}, NULL, NULL
+            FROM documents
+            JOIN title_trigrams ON title_trigrams.document_id = documents.id
+            WHERE title_trigrams MATCH ? {filter}
+        "})?;
+        let query = trigrams.join(" OR ");
+        let params = std::iter::once(&query as &dyn ToSql).chain(filter_params.iter().copied());
+
+        let mut matches: Vec<(usize, Entry)> = match_title_trigrams
+            .query_map(params_from_iter(params), Entry::from_row)?
+            .map(|row| row.unwrap())
+            .filter_map(|entry| Some((title_distance(&words, &entry.title)?, entry)))
+            .collect();
+        matches.sort_by_key(|(distance, _)| *distance);
+        Ok(matches.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Sets the link targets of each entry. Wiki links resolve to the uri of the document with
+    /// that title, preferring one from the same collection, or are left as the page name if there
+    /// isn't one. Other links are their url.
+    fn add_links(&self, entries: &mut [Entry]) -> Result<(), rusqlite::Error> {
+        let mut select_links = self.connection.prepare_cached(indoc! {"
+            SELECT url, page, collection
+            FROM document_links
+            JOIN documents ON documents.id = document_links.document_id
+            WHERE uri = ?
+            ORDER BY position
+        "})?;
+        for entry in entries {
+            let rows = select_links.query_map([&entry.url], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get(2)?))
+            })?;
+            let mut links = vec![];
+            for row in rows {
+                let (url, page, collection): (_, _, Option<String>) = row?;
+                let Some(page) = page else {
+                    links.push(url);
+                    continue;
+                };
+                let target = match page_name(&page) {
+                    "" => Some(entry.url.clone()),
+                    name => self.resolve_page(name, collection.as_deref())?,
+                };
+                links.push(target.unwrap_or(page));
+            }
+            entry.links = Some(links);
+        }
+        Ok(())
+    }
+
+    /// Groups `entries` by their type, tags, vault or folder, keeping the order of the entries
+    /// within each group. Groups are sorted by name, followed by entries without one, such as
+    /// documents without a type or in the root folder. An entry appears under each of its tags,
+    /// though not under the parents of a nested tag it has.
+    pub fn group<'e>(
+        &self, entries: &'e [Entry], by: GroupBy,
+    ) -> Result<Vec<Group<'e>>, rusqlite::Error> {
+        let mut select_names = self.connection.prepare_cached(match by {
+            GroupBy::Type => "SELECT type FROM documents WHERE uri = ?",
+            GroupBy::Vault => "SELECT collection FROM documents WHERE uri = ?",
+            GroupBy::Folder => "SELECT nullif(rtrim(folder, '/'), '') FROM documents WHERE uri = ?",
+            GroupBy::Tag => indoc! {"
+                SELECT tag FROM document_tags
+                JOIN documents ON documents.id = document_tags.document_id
+                WHERE uri = ? AND NOT EXISTS (
+                    SELECT 1 FROM document_tags AS child
+                    WHERE child.document_id = document_tags.document_id
+                    AND instr(child.tag, document_tags.tag || '/') = 1
+                )
+            "},
+        })?;
+
+        let mut groups: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+        let mut ungrouped = vec![];
+        for entry in entries {
+            let names = select_names.query_map([&entry.url], |row| row.get(0))?;
+            let names: Vec<Option<String>> = names.collect::<Result<_, _>>()?;
+            let names: Vec<String> = names.into_iter().flatten().collect();
+            if names.is_empty() {
+                ungrouped.push(entry);
+            }
+            for name in names {
+                groups.entry(name).or_default().push(entry);
+            }
+        }
+
+        let mut groups: Vec<Group> =
+            groups.into_iter().map(|(name, entries)| Group { name: Some(name), entries }).collect();
+        if !ungrouped.is_empty() {
+            groups.push(Group { name: None, entries: ungrouped });
+        }
+        Ok(groups)
+    }
+
+    /// The uri of the document a wiki link to the page `name` leads to: the one with that file
+    /// title or failing that alias, ignoring case, preferring one from `collection`.
+    fn resolve_page(
+        &self, name: &str, collection: Option<&str>,
+    ) -> Result<Option<String>, rusqlite::Error> {
+        let mut resolve_page = self.connection.prepare_cached(indoc! {"
+            SELECT uri FROM documents
+            WHERE file_title = ?1 COLLATE NOCASE
+            OR id IN (SELECT document_id FROM document_aliases WHERE alias = ?1 COLLATE NOCASE)
+            ORDER BY file_title = ?1 COLLATE NOCASE DESC, collection = ?2 DESC, id
+            LIMIT 1
+        "})?;
+        resolve_page.query_row((name, collection), |row| row.get(0)).optional()
+    }
+
+    /// The documents with wiki links to `target`, which can be a document's path, absolute or
+    /// within its vault, or a title or alias as it would be written in a link. Each link is
+    /// resolved as it would be when followed, so a link to a page with the same name in another
+    /// vault isn't counted.
+    pub fn linking_to(&self, target: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+        let by_path: Option<String> = self
+            .connection
+            .query_row(
+                indoc! {"
+                    SELECT uri FROM documents
+                    WHERE path = ?1 OR substr(path, -length(?1) - 1) = '/' || ?1
+                    ORDER BY path = ?1 DESC, id
+                    LIMIT 1
+                "},
+                [target],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let by_title = || {
+            self.connection
+                .query_row(
+                    "SELECT uri FROM documents WHERE title = ?1 COLLATE NOCASE ORDER BY id LIMIT 1",
+                    [target],
+                    |row| row.get(0),
+                )
+                .optional()
+        };
+        let uri = match by_path {
+            Some(uri) => uri,
+            None => match self.resolve_page(target, None)? {
+                Some(uri) => uri,
+                None => by_title()?.ok_or_else(|| format!("No document matches `{target}`"))?,
+            },
+        };
+
+        // Only links written with one of the target's names can resolve to it.
+        let mut select_names = self.connection.prepare(indoc! {"
+            SELECT file_title FROM documents WHERE uri = ?1 AND file_title IS NOT NULL
+            UNION
+            SELECT alias FROM document_aliases
+            JOIN documents ON documents.id = document_aliases.document_id
+            WHERE uri = ?1
+        "})?;
+        let names: Vec<String> =
+            select_names.query_map([&uri], |row| row.get(0))?.collect::<Result<_, _>>()?;
+
+        let mut select_links = self.connection.prepare(indoc! {"
+            SELECT document_id, collection, page
+            FROM document_links
+            JOIN documents ON documents.id = document_links.document_id
+            WHERE page IS NOT NULL
+            ORDER BY coalesce(path, uri), position
+        "})?;
+        let mut ids: Vec<i64> = vec![];
+        let mut rows = select_links.query([])?;
+        while let Some(row) = rows.next()? {
+            let (id, collection, page): (i64, Option<String>, String) =
+                (row.get(0)?, row.get(1)?, row.get(2)?);
+            let name = page_name(&page);
+            if ids.contains(&id) || !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            if self.resolve_page(name, collection.as_deref())?.as_ref() == Some(&uri) {
+                ids.push(id);
+            }
+        }
+
+        let mut select_entry = self.connection.prepare(indoc! {"
+            SELECT uri, title, markdown, type, created, modified, path, file_title, id, NULL, NULL
+            FROM documents
+            WHERE id = ?
+        "})?;
+        let entries = ids
+            .into_iter()
+            .map(|id| select_entry.query_row([id], Entry::from_row))
+            .collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+
+    /// Every document with wiki links to pages that aren't in the index, along with those pages
+    /// in the order they're linked. Links to headings within the same document are never broken.
+    pub fn broken_links(&self) -> Result<Vec<BrokenLinks>, rusqlite::Error> {
+        let mut select_links = self.connection.prepare(indoc! {"
+            SELECT uri, title, path, collection, page
+            FROM document_links
+            JOIN documents ON documents.id = document_links.document_id
+            WHERE page IS NOT NULL
+            ORDER BY coalesce(path, uri), position
+        "})?;
+        let rows = select_links.query_map([], |row| {
+            let document = BrokenLinks {
+                url: row.get(0)?,
+                title: row.get(1)?,
+                path: row.get(2)?,
+                pages: vec![],
+            };
+            Ok((document, row.get::<_, Option<String>>(3)?, row.get::<_, String>(4)?))
+        })?;
+
+        let mut broken: Vec<BrokenLinks> = vec![];
+        let mut attachments = HashMap::new();
+        for row in rows {
+            let (document, collection, page) = row?;
+            let name = page_name(&page);
+            if name.is_empty() || self.resolve_page(name, collection.as_deref())?.is_some() {
+                continue;
+            }
+            if let Some(file) = attachment_name(&page) {
+                let files = attachments
+                    .entry(collection.clone())
+                    .or_insert_with(|| self.file_names(collection.as_deref()));
+                if files.contains(&file.to_lowercase()) {
+                    continue;
+                }
+            }
+            match broken.last_mut() {
+                Some(last) if last.url == document.url => {
+                    if !last.pages.contains(&page) {
+                        last.pages.push(page)
+                    }
+                }
+                _ => broken.push(BrokenLinks { pages: vec![page], ..document }),
+            }
+        }
+        Ok(broken)
+    }
+
+    /// The lowercased names of every file in the folder of the collection named `collection`,
+    /// which links to attachments such as images can point to.
+    fn file_names(&self, collection: Option<&str>) -> HashSet<String> {
+        let root = self
+            .collections
+            .iter()
+            .find(|other| Some(other.name().as_str()) == collection)
+            .and_then(|collection| collection.root());
+        root.into_iter()
+            .flat_map(WalkDir::new)
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+            .collect()
+    }
+
+    /// Figures about the indexed documents, with the `limit` most common terms, tags and types.
+    pub fn stats(&self, limit: usize) -> Result<Stats, rusqlite::Error> {
+        // A view of the terms in the word index with how often each appears in each column.
+        self.connection.execute(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS temp.word_index_vocab USING fts5vocab(main, word_index, col)",
+            (),
+        )?;
+        let counts = |sql: &str| -> Result<Vec<Count>, rusqlite::Error> {
+            let mut select = self.connection.prepare(sql)?;
+            let rows = select.query_map([limit], |row| {
+                Ok(Count { name: row.get(0)?, documents: row.get(1)? })
+            })?;
+            rows.collect()
+        };
+
+        let documents = self.size();
+        let bytes: i64 = self.connection.query_row(
+            "SELECT coalesce(sum(length), 0) FROM documents",
+            [],
+            |row| row.get(0),
+        )?;
+        let (words, distinct_terms): (i64, i64) = self.connection.query_row(
+            "SELECT coalesce(sum(cnt), 0), count(*) FROM word_index_vocab WHERE col = 'text'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let size = self
+            .path()
+            .filter(|path| !path.is_empty())
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len());
+
+        Ok(Stats {
+            documents,
+            bytes,
+            words,
+            average_words: if documents > 0 { words as f64 / documents as f64 } else { 0.0 },
+            distinct_terms,
+            terms: counts(indoc! {"
+                SELECT term, doc FROM word_index_vocab WHERE col = 'text'
+                ORDER BY doc DESC, term LIMIT ?
+            "})?,
+            tags: counts(indoc! {"
+                SELECT tag, count(*) FROM document_tags GROUP BY tag
+                ORDER BY count(*) DESC, tag LIMIT ?
+            "})?,
+            // Types are matched without case, so `Person` and `person` are counted together.
+            types: counts(indoc! {"
+                SELECT min(type), count(*) FROM documents WHERE type IS NOT NULL
+                GROUP BY type COLLATE NOCASE
+                ORDER BY count(*) DESC, min(type) LIMIT ?
+            "})?,
+            size,
+        })
+    }
+
+    /// Documents similar to the best match for `query`, not including that document. Documents
+    /// sharing more of its tags come first, then those best matching its most distinctive words:
+    /// the ones it uses often that few other documents do.
+    pub fn similar(
+        &self, query: &str, options: &SearchOptions,
+    ) -> Result<SearchResults, Box<dyn std::error::Error>> {
+        let best = SearchOptions { limit: Some(1), ..Default::default() };
+        let Some(target) = self.search_with(query, &best)?.entries.into_iter().next() else {
+            return Err(format!("No document matches `{query}`").into());
+        };
+        let (id, text): (i64, String) = self.connection.query_row(
+            indoc! {"
+                SELECT documents.id, text
+                FROM documents
+                JOIN word_index ON word_index.document_id = documents.id
+                WHERE uri = ?
+            "},
+            [&target.url],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let plan = self.plan("", options)?;
+        let columns = &plan.columns;
+        let filter = plan.filter();
+        let filter_params = plan.filter_params();
+
+        let mut results: Vec<Entry> = vec![];
+        let keywords = self.keywords(&text)?;
+        if !keywords.is_empty() {
+            let match_keywords = format!("{{text}} : {}", keywords.join(" OR "));
+            let mut match_word_index = self.connection.prepare(&formatdoc! {"
+                SELECT {columns}, NULL, rank
+                FROM documents
+                JOIN word_index ON word_index.document_id = documents.id
+                WHERE word_index MATCH ? AND documents.id != ? {filter}
+                ORDER BY rank, modified DESC, uri
+            "})?;
+            let params =
+                [&match_keywords as &dyn ToSql, &id].into_iter().chain(filter_params.clone());
+            results = match_word_index
+                .query_map(params_from_iter(params), Entry::from_row)?
+                .collect::<Result<_, _>>()?;
+        }
+
+        let mut match_shared_tags = self.connection.prepare(&formatdoc! {"
+            SELECT {columns}, NULL, NULL, count(*) AS shared
+            FROM documents
+            JOIN document_tags ON document_tags.document_id = documents.id
+            WHERE tag IN (SELECT tag FROM document_tags WHERE document_id = ?)
+                AND documents.id != ? {filter}
+            GROUP BY documents.id
+        "})?;
+        let mut shared_tags = std::collections::HashMap::new();
+        let params = [&id as &dyn ToSql, &id].into_iter().chain(filter_params);
+        let rows = match_shared_tags.query_map(params_from_iter(params), |row| {
+            Ok((Entry::from_row(row)?, row.get::<_, usize>(11)?))
+        })?;
+        for row in rows {
+            let (entry, shared) = row?;
+            shared_tags.insert(entry.url.clone(), shared);
+            if !results.iter().any(|result| result.url == entry.url) {
+                results.push(entry);
+            }
+        }
+        results.sort_by_key(|entry| std::cmp::Reverse(shared_tags.get(&entry.url).copied()));
+
+        if let Some(min_score) = options.min_score {
+            results.retain(|entry| entry.score.is_none_or(|score| score >= min_score));
+        }
+
+        if let Some(limit) = options.limit {
+            results.truncate(limit);
+        }
+
+        if options.with_links {
+            self.add_links(&mut results)?;
+        }
+
+        Ok(SearchResults { entries: results })
+    }
+
+    /// The words of `text` that best distinguish it from other documents, as FTS5 strings. Words
+    /// are weighted by how often `text` uses them and how few documents contain them, and those
+    /// found in no other document are left out as they can't match anything else.
+    fn keywords(&self, text: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut counts = std::collections::HashMap::<String, usize>::new();
+        for word in text.split(|c: char| !c.is_alphanumeric()) {
+            if word.chars().count() >= MIN_KEYWORD_LENGTH {
+                *counts.entry(word.to_lowercase()).or_default() += 1;
+            }
+        }
+
+        let mut count_documents = self.connection.prepare(indoc! {"
+            SELECT count(*) FROM word_index WHERE word_index MATCH ?
+        "})?;
+        let total = self.size() as f64;
+        let mut weighted: Vec<(f64, String)> = vec![];
+        for (word, count) in counts {
+            let keyword = format!("\"{word}\"");
+            let documents: f64 =
+                count_documents.query_row([format!("{{text}} : {keyword}")], |row| row.get(0))?;
+            if documents > 1.0 {
+                weighted.push((count as f64 * (total / documents).ln(), keyword));
+            }
+        }
+        weighted.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        Ok(weighted.into_iter().take(SIMILAR_KEYWORDS).map(|(_, keyword)| keyword).collect())
+    }
+}
+
+/// The parts of a search query, ready to be run.
+struct Plan<'q> {
+    words: Vec<&'q str>,
+    /// Each word as an FTS5 prefix query, or for substring searches a phrase.
+    parts: Vec<String>,
+    /// The full-text table the parts are matched against.
+    table: &'static str,
+    /// SQL clauses restricting the documents matched, each with the values of its parameters.
+    filters: Vec<(&'static str, Vec<Box<dyn ToSql>>)>,
+    /// The columns an `Entry` is read from.
+    columns: String,
+    /// Whether every word was dropped for being too short, so no documents should match.
+    matches_nothing: bool,
+    /// Whether documents match with any of the parts, rather than all of them.
+    match_any: bool,
+}
+
+impl Plan<'_> {
+    /// The filter clauses, to follow a WHERE condition.
+    fn filter(&self) -> String {
+        self.filters.iter().map(|(clause, _)| *clause).collect()
+    }
+
+    /// The values of every filter's parameters, in the order they appear in `filter`.
+    fn filter_params(&self) -> Vec<&dyn ToSql> {
+        self.filters.iter().flat_map(|(_, params)| params.iter().map(Box::as_ref)).collect()
+    }
+
+    fn match_title(&self) -> String {
+        self.match_column("title")
+    }
+
+    fn match_text(&self) -> String {
+        self.match_column("text")
+    }
+
+    /// The parts restricted to `column`. Alternatives are grouped in brackets, so the column
+    /// applies to each of them rather than only the first.
+    fn match_column(&self, column: &str) -> String {
+        if self.match_any && self.parts.len() > 1 {
+            format!("{{{column}}} : ({})", self.parts.join(" OR "))
+        } else {
+            format!("{{{column}}} : {}", self.parts.join(" "))
+        }
+    }
+}
+
+/// The field and value of a `type:`, `tag:` or `title:` qualifier, with the field lowercase.
+fn qualifier(term: &str) -> Option<(&'static str, &str)> {
+    let (field, value) = term.split_once(':')?;
+    let field = ["type", "tag", "title"].into_iter().find(|f| f.eq_ignore_ascii_case(field))?;
+    (!value.is_empty()).then_some((field, value))
+}
+
+/// `text` as an FTS5 phrase, quoted so none of it is read as query syntax.
+fn phrase(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// Splits a query into its terms on spaces, keeping each `near:N(...)` group whole.
+fn query_terms(query: &str) -> Vec<&str> {
+    let mut terms = vec![];
+    let mut start = 0;
+    let mut in_group = false;
+    for (i, c) in query.char_indices() {
+        match c {
+            '(' if !in_group => in_group = near_distance(&query[start..i]).is_some(),
+            ')' => in_group = false,
+            ' ' if !in_group => {
+                terms.push(&query[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    terms.push(&query[start..]);
+    terms.retain(|term| !term.is_empty());
+    terms
+}
+
+/// The distance in a `near` or `near:N` prefix, ignoring case. Without a number, FTS5's default
+/// of 10 tokens is used.
+fn near_distance(prefix: &str) -> Option<usize> {
+    let distance = prefix.get(..4).filter(|near| near.eq_ignore_ascii_case("near"))?;
+    match &prefix[distance.len()..] {
+        "" => Some(10),
+        rest => rest.strip_prefix(':')?.parse().ok(),
+    }
+}
+
+/// A `near:N(first second ...)` group as an FTS5 NEAR query, matching documents where the words
+/// are all within N tokens of each other. Unlike bare words, each must match a whole word.
+fn near_query(term: &str) -> Option<String> {
+    let (prefix, rest) = term.split_once('(')?;
+    let distance = near_distance(prefix)?;
+    let phrases: Vec<String> =
+        rest.strip_suffix(')')?.split(' ').filter(|word| !word.is_empty()).map(phrase).collect();
+    (!phrases.is_empty()).then(|| format!("NEAR({}, {distance})", phrases.join(" ")))
+}
+
+/// The name of the page a wiki link points to, without any folder, extension or heading, so
+/// `[[folder/Page.md#Part]]` is to `Page`. Like Obsidian, the extension can be in any case. Empty
+/// for links to a heading in the same document.
+fn page_name(page: &str) -> &str {
+    let name = page.split('#').next().unwrap_or_default();
+    let name = name.rsplit('/').next().unwrap_or_default();
+    let stem = name.len().saturating_sub(".md".len());
+    match name.get(stem..) {
+        Some(extension) if extension.eq_ignore_ascii_case(".md") => &name[..stem],
+        _ => name,
+    }
+}
+
+/// The file name a wiki link points to when it's to an attachment, with an extension other than
+/// `.md`, so `[[assets/image.png]]` is to `image.png`.
+fn attachment_name(page: &str) -> Option<&str> {
+    let name = page.split('#').next()?.rsplit('/').next()?;
+    let (_, extension) = name.rsplit_once('.')?;
+    (!extension.is_empty() && !extension.eq_ignore_ascii_case("md")).then_some(name)
+}
+
+/// A query parameter's value as it would appear in SQL.
+fn describe(param: &dyn ToSql) -> String {
+    let value = match param.to_sql() {
+        Ok(ToSqlOutput::Borrowed(value)) => value.into(),
+        Ok(ToSqlOutput::Owned(value)) => value,
+        _ => return "?".to_string(),
+    };
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::Real(value) => value.to_string(),
+        Value::Text(value) => format!("'{}'", value.replace('\'', "''")),
+        Value::Blob(value) => format!("<{} bytes>", value.len()),
+    }
+}
+
+/// Maps an FTS5 bm25 rank, where more negative is a better match, onto a score from 0 to 1
+/// where higher is better.
+fn score(rank: f64) -> f64 {
+    let relevance = (-rank).max(0.0);
+    relevance / (1.0 + relevance)
+}
+
+/// Whether `error` means the database file isn't a database, or is damaged.
+fn is_corrupt(error: &rusqlite::Error) -> bool {
+    matches!(error.sqlite_error_code(), Some(ErrorCode::NotADatabase | ErrorCode::DatabaseCorrupt))
+}
+
+/// Runs `f`, retrying with an increasing delay while another process keeps the database locked
+/// for longer than the busy timeout.
+fn with_retry<T>(mut f: impl FnMut() -> Result<T, rusqlite::Error>) -> Result<T, rusqlite::Error> {
+    let mut delay = Duration::from_millis(50);
+    for _ in 0..BUSY_RETRIES {
+        match f() {
+            Err(rusqlite::Error::SqliteFailure(error, _))
+                if matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                info!("Database is busy, retrying in {:?}", delay);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    f()
+}
+
+/// Runs `f` again, with freshly prepared statements, if it failed because the schema changed
+/// under it and `retryable` still allows it. SQLite re-prepares statements itself when it can, but
+/// gives up if the schema keeps changing, or a table is missing while another process rebuilds it.
+fn with_reprepare<T>(
+    connection: &Connection, retryable: impl Fn() -> bool,
+    mut f: impl FnMut() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    match f() {
+        Err(error) if error.downcast_ref().is_some_and(is_stale) && retryable() => {
+            info!("Schema changed while searching, retrying: {error}");
+            connection.flush_prepared_statement_cache();
+            f()
+        }
+        result => result,
+    }
+}
+
+/// Whether `error` comes from a statement prepared against an older schema.
+fn is_stale(error: &rusqlite::Error) -> bool {
+    match error {
+        rusqlite::Error::SqliteFailure(error, message) => {
+            error.code == ErrorCode::SchemaChanged
+                || message.as_deref().is_some_and(|message| message.starts_with("no such table"))
+        }
+        _ => false,
+    }
+}
+
+/// Every run of three characters in `word`.
+fn trigrams(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    chars.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// The total edit distance from each query word to its closest word in `title`, or None if any
+/// query word is too far from every title word. A word may be a third of its length out.
+fn title_distance(words: &[String], title: &str) -> Option<usize> {
+    let title = title.to_lowercase();
+    let title_words: Vec<&str> =
+        title.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+    words
+        .iter()
+        .map(|word| {
+            let distance =
+                title_words.iter().map(|title_word| edit_distance(word, title_word)).min()?;
+            (distance <= (word.chars().count() / 3).max(1)).then_some(distance)
+        })
+        .sum()
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A tag followed by each of its parents, from the most to least specific: `a/b/c`, `a/b`, `a`.
+fn tag_hierarchy(tag: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(tag).chain(tag.rmatch_indices('/').map(|(index, _)| &tag[..index]))
+}
+
+/// The folder holding `path` within `root`, with each part followed by `/`, so a prefix of it
+/// is always a whole folder: `Projects/Acme/`. Empty for documents directly in `root`.
+fn folder(path: &Path, root: &Path) -> Option<String> {
+    let parent = path.parent()?.strip_prefix(root).ok()?;
+    Some(parent.iter().map(|part| format!("{}/", part.to_string_lossy())).collect())
+}
+
+#[allow(dead_code)]
+trait OtherToSql {
+    fn to_sql(&self) -> &str;
+}
+
+impl OtherToSql for &PathBuf {
+    fn to_sql(&self) -> &str {
+        self.to_str().unwrap()
+    }
+}
+
+impl<T: OtherToSql> OtherToSql for Option<T> {
+    fn to_sql(&self) -> &str {
+        match self {
+            Some(value) => value.to_sql(),
+            None => "",
+        }
+    }
+}
+
+/// A document found in the index, as written in JSON output. Fields not chosen with `--fields`
+/// are left out.
+#[derive(Debug, Serialize, JsonSchema, PartialEq)]
+pub struct Entry {
+    /// The document's id in the index, which stays the same as long as the document does. None
+    /// for entries that weren't read from the index.
+    id: Option<i64>,
+    title: String,
+    /// The title from the file name, which may differ from a front matter `title`.
+    file_title: Option<String>,
+    url: String,
+    path: Option<String>,
+    #[serde(rename = "type")]
+    doc_type: Option<String>,
+    markdown: String,
+    created: DateTime<Utc>,
+    modified: DateTime<Utc>,
+    /// How well the document matched the query, from 0 to 1 where higher is better. Only set
+    /// for full-text matches.
+    score: Option<f64>,
+    /// An excerpt of the text around the matched terms, which are wrapped in `SNIPPET_START`
+    /// and `SNIPPET_END`.
+    #[serde(skip)]
+    snippet: Option<String>,
+    /// Where each of the document's links points, when asked for with `SearchOptions::with_links`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    links: Option<Vec<String>>,
+    /// The SHA-256 of the document's source, only set for documents looked up by uri or id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+impl Entry {
+    pub fn new(
+        url: String, title: String, markdown: String, doc_type: Option<String>,
+        created: DateTime<Utc>, modified: DateTime<Utc>, path: Option<String>,
+    ) -> Entry {
+        Entry {
+            id: None,
+            title,
+            file_title: None,
+            url,
+            path,
+            doc_type,
+            markdown,
+            created,
+            modified,
+            score: None,
+            snippet: None,
+            links: None,
+            checksum: None,
+        }
+    }
+
+    fn from_row(row: &rusqlite::Row) -> Result<Entry, rusqlite::Error> {
+        let mut entry = Entry::new(
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get(6)?,
+        );
+        entry.file_title = row.get(7)?;
+        entry.id = row.get(8)?;
+        entry.snippet = row.get(9)?;
+        entry.score = row.get::<_, Option<f64>>(10)?.map(score);
+        Ok(entry)
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        self.id
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.url
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn file_title(&self) -> Option<&str> {
+        self.file_title.as_deref()
+    }
+
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    pub fn score(&self) -> Option<f64> {
+        self.score
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn snippet(&self) -> Option<&str> {
+        self.snippet.as_deref()
+    }
+
+    pub fn links(&self) -> Option<&[String]> {
+        self.links.as_deref()
+    }
+
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.uri())
+    }
+}
+
+/// A document's wiki links to pages that don't exist.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BrokenLinks {
+    pub title: String,
+    pub url: String,
+    pub path: Option<String>,
+    /// The pages linked to, as they're written in the links.
+    pub pages: Vec<String>,
+}
+
+/// How many documents a refresh added, updated, removed or left alone.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct Changes {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Documents that couldn't be indexed, which keep any earlier version.
+    pub skipped: usize,
+}
+
+/// Figures describing the index, from `Index::stats`.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Stats {
+    pub documents: i64,
+    /// The combined length of the documents' sources, in bytes.
+    pub bytes: i64,
+    /// Words indexed across every document, including their titles.
+    pub words: i64,
+    pub average_words: f64,
+    /// Different words in the index, after stemming.
+    pub distinct_terms: i64,
+    /// The terms found in the most documents.
+    pub terms: Vec<Count>,
+    /// The most used tags. A parent tag counts the documents with any of its children.
+    pub tags: Vec<Count>,
+    pub types: Vec<Count>,
+    /// The size in bytes of the database file, or None for an in-memory index.
+    pub size: Option<u64>,
+}
+
+/// How many documents have a term, tag or type.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Count {
+    pub name: String,
+    pub documents: i64,
+}
+
+/// Restrictions applied to search results on top of the query itself.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchOptions {
+    /// Only match documents from the collection with this name.
+    pub vault: Option<String>,
+    /// Only match documents with a fenced code block in this language.
+    pub language: Option<String>,
+    /// Only match documents in this folder of their collection, or any folder inside it, such as
+    /// `Projects/Acme`.
+    pub folder: Option<String>,
+    /// Only match documents with each of these front matter keys set to the value, ignoring case.
+    /// A list matches if any of its items does.
+    pub metadata: Vec<(String, String)>,
+    /// Only match documents of this type, ignoring case.
+    pub doc_type: Option<String>,
+    /// Leave out documents with any of these tags, or tags inside them.
+    pub exclude_tags: Vec<String>,
+    /// Leave out documents of any of these types, ignoring case.
+    pub exclude_types: Vec<String>,
+    pub modified_since: Option<DateTime<Utc>>,
+    pub created_since: Option<DateTime<Utc>>,
+    /// Only match documents created or modified after this.
+    pub changed_since: Option<DateTime<Utc>>,
+    /// Leave out documents marked `publish: false` or `draft: true` in their front matter.
+    pub published_only: bool,
+    /// With `published_only`, also leave out documents that set neither.
+    pub unmarked_as_drafts: bool,
+    /// When nothing matches, fall back to titles within a small edit distance of the query.
+    pub fuzzy: bool,
+    /// Ignore words of the query with fewer characters than this, as short words match so many
+    /// others by prefix. If every word is ignored nothing matches.
+    pub min_term_length: usize,
+    /// Match only whole words, so `cat` doesn't match `category`. By default each word of the
+    /// query also matches words it's the start of.
+    pub exact: bool,
+    /// Search for the whole query as one literal phrase of whole words, without reading any of it
+    /// as qualifiers, tags or near groups.
+    pub fixed_string: bool,
+    /// Match documents with any of the words of the query, rather than all of them. Those with
+    /// more of the words still rank higher.
+    pub match_any: bool,
+    /// Match each word anywhere within words of the text, not just at their start. Words of
+    /// fewer than three characters can't match this way.
+    pub substring: bool,
+    /// Only match the query against titles, skipping the text.
+    pub title_only: bool,
+    /// Drop full-text matches scoring below this, on the 0 to 1 scale of `Entry::score`.
+    pub min_score: Option<f64>,
+    /// Return at most this many results.
+    pub limit: Option<usize>,
+    /// Include the targets of each result's links.
+    pub with_links: bool,
+    /// The fields that will be used from each result, or None for all of them. Only the
+    /// markdown is left out when not wanted, as it's the largest.
+    pub fields: Option<Vec<Field>>,
+}
+
+/// A field of an `Entry`, for choosing which are output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Title,
+    FileTitle,
+    Url,
+    Path,
+    Type,
+    Markdown,
+    Created,
+    Modified,
+    Score,
+    Links,
+    Checksum,
+}
+
+impl Field {
+    pub const ALL: [Field; 12] = [
+        Field::Id,
+        Field::Title,
+        Field::FileTitle,
+        Field::Url,
+        Field::Path,
+        Field::Type,
+        Field::Markdown,
+        Field::Created,
+        Field::Modified,
+        Field::Score,
+        Field::Links,
+        Field::Checksum,
+    ];
+
+    /// The name of the field in serialized entries.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Title => "title",
+            Field::FileTitle => "file_title",
+            Field::Url => "url",
+            Field::Path => "path",
+            Field::Type => "type",
+            Field::Markdown => "markdown",
+            Field::Created => "created",
+            Field::Modified => "modified",
+            Field::Score => "score",
+            Field::Links => "links",
+            Field::Checksum => "checksum",
+        }
+    }
+
+    /// Every field except the markdown, which is often much larger than the rest combined.
+    pub fn defaults() -> Vec<Field> {
+        Field::ALL.into_iter().filter(|field| *field != Field::Markdown).collect()
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Field::ALL.into_iter().find(|field| field.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Field::ALL.iter().map(Field::name).collect();
+            format!("unknown field `{name}`, expected one of: {}", names.join(", "))
+        })
+    }
+}
+
+/// What search results can be grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Type,
+    Tag,
+    Vault,
+    Folder,
+}
+
+impl GroupBy {
+    pub const ALL: [GroupBy; 4] = [GroupBy::Type, GroupBy::Tag, GroupBy::Vault, GroupBy::Folder];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupBy::Type => "type",
+            GroupBy::Tag => "tag",
+            GroupBy::Vault => "vault",
+            GroupBy::Folder => "folder",
+        }
+    }
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        GroupBy::ALL.into_iter().find(|by| by.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = GroupBy::ALL.iter().map(GroupBy::name).collect();
+            format!("unknown grouping `{name}`, expected one of: {}", names.join(", "))
+        })
+    }
+}
+
+/// Search results sharing a type, tag, vault or folder, named by it. Entries without one are
+/// grouped together with no name.
+#[derive(Debug)]
+pub struct Group<'e> {
+    pub name: Option<String>,
+    pub entries: Vec<&'e Entry>,
+}
+
+/// Serializes only the chosen fields of an entry, in the order they were chosen. Links are left
+/// out when they weren't asked for, as they are when serializing an `Entry`.
+pub struct Projection<'a> {
+    pub entry: &'a Entry,
+    pub fields: &'a [Field],
+}
+
+impl Serialize for Projection<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let entry = self.entry;
+        let mut map = serializer.serialize_map(None)?;
+        for field in self.fields {
+            match field {
+                Field::Id => map.serialize_entry(field.name(), &entry.id)?,
+                Field::Title => map.serialize_entry(field.name(), &entry.title)?,
+                Field::FileTitle => map.serialize_entry(field.name(), &entry.file_title)?,
+                Field::Url => map.serialize_entry(field.name(), &entry.url)?,
+                Field::Path => map.serialize_entry(field.name(), &entry.path)?,
+                Field::Type => map.serialize_entry(field.name(), &entry.doc_type)?,
+                Field::Markdown => map.serialize_entry(field.name(), &entry.markdown)?,
+                Field::Created => map.serialize_entry(field.name(), &entry.created)?,
+                Field::Modified => map.serialize_entry(field.name(), &entry.modified)?,
+                Field::Score => map.serialize_entry(field.name(), &entry.score)?,
+                Field::Links => {
+                    if let Some(links) = &entry.links {
+                        map.serialize_entry(field.name(), links)?
+                    }
+                }
+                Field::Checksum => {
+                    if let Some(checksum) = &entry.checksum {
+                        map.serialize_entry(field.name(), checksum)?
+                    }
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+/// The documents matching a search, best first.
+#[derive(Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct SearchResults {
+    entries: Vec<Entry>,
+}
+
+impl SearchResults {
+    pub fn entries(&self) -> &Vec<Entry> {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::markdown::collection::DocumentsIter;
+    use crate::markdown::source::{self, NamedSource};
+    use crate::markdown::{DialectDocument, Obsidian, Source};
+    use crate::obsidian::Vault;
+    use crate::test::TestDir;
+    use std::time::{Duration, SystemTime};
+
+    /// A fixed point in time, `days` after the epoch.
+    fn at(days: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(days * 24 * 60 * 60)
+    }
+
+    #[test]
+    fn ensure_schema_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let connection = Connection::open_in_memory()?;
+        assert!(Index::ensure_schema_version(&connection).unwrap());
+        assert!(!Index::ensure_schema_version(&connection).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn schema_indexes_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let index = Index::open_in_memory(vec![]);
+        let plan = |sql: &str| -> Result<String, rusqlite::Error> {
+            index.connection.query_row(&format!("EXPLAIN QUERY PLAN {sql}"), [], |row| row.get(3))
+        };
+
+        for (sql, expected) in [
+            ("SELECT uri FROM documents WHERE title = 'Note'", "documents_title"),
+            ("SELECT uri FROM documents WHERE modified >= '2023-01-01'", "documents_modified"),
+            ("SELECT uri FROM documents WHERE created >= '2023-01-01'", "documents_created"),
+            ("SELECT uri FROM documents ORDER BY title", "documents_title"),
+        ] {
+            let plan = plan(sql)?;
+            assert!(plan.contains(expected), "{sql} should use {expected}: {plan}");
+        }
+
+        // Filters are checked as search runs them, against the clauses and values of its plan.
+        let type_option = SearchOptions { doc_type: Some("Person".into()), ..Default::default() };
+        let metadata = vec![("Status".to_string(), "Active".to_string())];
+        let metadata_option = SearchOptions { metadata, ..Default::default() };
+        for (query, options, expected) in [
+            ("type:person", SearchOptions::default(), "documents_type"),
+            ("", type_option, "documents_type"),
+            ("", metadata_option, "document_metadata_key_value"),
+        ] {
+            let search = index.plan(query, &options)?;
+            let (filter, params) = (search.filter(), search.filter_params());
+            let sql = format!("EXPLAIN QUERY PLAN SELECT uri FROM documents WHERE 1 {filter}");
+            let mut statement = index.connection.prepare(&sql)?;
+            let plan: Vec<String> = statement
+                .query_map(params_from_iter(params), |row| row.get(3))?
+                .collect::<Result<_, _>>()?;
+            let plan = plan.join("\n");
+            assert!(plan.contains(expected), "{options:?} should use {expected}: {plan}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn search_after_reset_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let database = TestDir::new();
+        let path = database.path().join("index.sqlite");
+        let collections =
+            || -> Vec<Box<dyn Collection>> { vec![Box::new(dir.path().to_path_buf())] };
+
+        dir.write("old.md", "Old document")?;
+        let mut index = Index::open_from_file(collections(), &path)?;
+        index.refresh()?;
+        assert_eq!(1, index.search("document")?.len());
+
+        dir.delete("old.md")?;
+        dir.write("new.md", "New document")?;
+        let mut other = Index::open_from_file(collections(), &path)?;
+        other.reset()?;
+        assert!(index.search("document")?.is_empty(), "search should see the reset");
+        other.refresh()?;
+
+        let results = index.search("document")?;
+        assert_eq!(1, results.len(), "search should see the rebuilt index");
+        assert_eq!("new", results.entries()[0].title());
+        Ok(())
+    }
+
+    #[test]
+    fn with_reprepare_tests() {
+        let connection = Connection::open_in_memory().unwrap();
+        let failure = |code, message: Option<&str>| -> Box<dyn std::error::Error> {
+            Box::new(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(code),
+                message.map(str::to_string),
+            ))
+        };
+
+        let mut attempts = 0;
+        let result = with_reprepare(
+            &connection,
+            || true,
+            || {
+                attempts += 1;
+                if attempts == 1 {
+                    Err(failure(rusqlite::ffi::SQLITE_SCHEMA, None))
+                } else {
+                    Ok(attempts)
+                }
+            },
+        );
+        assert_eq!(2, result.unwrap(), "a schema change should be retried");
+
+        let mut attempts = 0;
+        let result: Result<(), _> = with_reprepare(
+            &connection,
+            || true,
+            || {
+                attempts += 1;
+                Err(failure(rusqlite::ffi::SQLITE_ERROR, Some("no such table: word_index")))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(2, attempts, "a missing table should only be retried once");
+
+        let mut attempts = 0;
+        let result: Result<(), _> = with_reprepare(
+            &connection,
+            || true,
+            || {
+                attempts += 1;
+                Err(failure(rusqlite::ffi::SQLITE_ERROR, Some("fts5: syntax error")))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(1, attempts, "other errors shouldn't be retried");
+
+        let mut attempts = 0;
+        let result: Result<(), _> = with_reprepare(
+            &connection,
+            || false,
+            || {
+                attempts += 1;
+                Err(failure(rusqlite::ffi::SQLITE_SCHEMA, None))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(1, attempts, "nothing should be retried once it isn't retryable");
+    }
+
+    #[test]
+    fn ensure_schema_tokenizer_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let connection = Connection::open_in_memory()?;
+        let config = IndexConfig { tokenizer: "trigram".to_string(), ..Default::default() };
+        assert!(Index::ensure_schema(&connection, &config)?);
+        assert!(!Index::ensure_schema(&connection, &config)?);
+        assert!(Index::ensure_schema_version(&connection)?, "a new tokenizer rebuilds the schema");
+        Ok(())
+    }
+
+    #[test]
+    fn index_fields_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = || -> Box<dyn Collection> {
+            Box::new(vec![NamedSource::new(
+                "Review",
+                "---\nSummary: A tale of lighthouses\nauthor: Virginia\n---\nFinished it today",
+            )])
+        };
+        let mut index = IndexBuilder::new().collection(documents()).build()?;
+        index.refresh()?;
+        assert!(index.search("lighthouses")?.is_empty(), "fields aren't searchable by default");
+
+        let mut index =
+            IndexBuilder::new().collection(documents()).index_field("summary").build()?;
+        index.refresh()?;
+        assert_eq!(1, index.search("lighthouses")?.len());
+        assert!(index.search("virginia")?.is_empty(), "only listed fields are searchable");
+
+        let connection = Connection::open_in_memory()?;
+        let config = IndexConfig { index_fields: vec!["author".to_string()], ..Default::default() };
+        assert!(Index::ensure_schema(&connection, &config)?);
+        assert!(Index::ensure_schema_version(&connection)?, "new fields rebuild the schema");
+
+        let fields = |fields: &[&str]| IndexConfig {
+            index_fields: fields.iter().map(|field| field.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(Index::ensure_schema(&connection, &fields(&["author", "summary"]))?);
+        assert!(
+            !Index::ensure_schema(&connection, &fields(&["Summary", "AUTHOR"]))?,
+            "the order and case of fields shouldn't rebuild the schema"
+        );
+
+        let database = TestDir::new();
+        let builder = || {
+            IndexBuilder::new().collection(documents()).path(database.path().join("index.sqlite"))
+        };
+        builder().index_field("summary").build()?.refresh()?;
+        let index = builder().build()?;
+        assert_eq!(1, index.size(), "opening without fields shouldn't rebuild the index");
+        assert_eq!(1, index.search("lighthouses")?.len(), "the stored fields are kept");
+        let index = builder().index_field("").build()?;
+        assert_eq!(0, index.size(), "an empty field clears them");
+        assert!(builder().build()?.config.index_fields.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn open_corrupt_database_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let database = TestDir::new();
+        let path = database.path().join("index.sqlite");
+        dir.write("note.md", "Still here")?;
+
+        let build = |auto_repair: bool| {
+            IndexBuilder::new()
+                .collection(Box::new(dir.path().to_path_buf()))
+                .path(&path)
+                .auto_repair(auto_repair)
+                .build()
+        };
+        build(true)?.refresh()?;
+        let garbage = "This is not a database, just some garbage text.";
+        database.write("index.sqlite", garbage)?;
+
+        assert!(build(false).is_err(), "without repair the error is returned");
+        assert!(Index::open_from_file(vec![], &path).is_err(), "opening a file doesn't panic");
+        assert_eq!(garbage, std::fs::read_to_string(&path)?, "without repair the file is kept");
+        let mut index = build(true)?;
+        assert_eq!(0, index.size(), "the database should be recreated empty");
+        index.refresh()?;
+        assert_eq!(1, index.search("still")?.len());
+
+        let moved: Vec<PathBuf> = std::fs::read_dir(database.path())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .filter(|path| {
+                path.as_ref().map_or(true, |path| {
+                    path.file_name().unwrap().to_string_lossy().starts_with("index.sqlite.corrupt-")
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        assert_eq!(1, moved.len(), "the corrupt file should be moved aside: {moved:?}");
+        assert_eq!(garbage, std::fs::read_to_string(&moved[0])?, "nothing should be deleted");
+
+        // Damage to the settings, read as the index is opened, is repaired the same way.
+        let page: u64 = index.connection.query_row(
+            "SELECT (rootpage - 1) * (SELECT page_size FROM pragma_page_size) FROM sqlite_master WHERE name = 'application'",
+            [],
+            |row| row.get(0),
+        )?;
+        drop(index);
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(page))?;
+        file.write_all(&[0xff; 512])?;
+        drop(file);
+        assert!(build(false).is_err(), "a damaged settings page is found on opening");
+        let mut index = build(true)?;
+        index.refresh()?;
+        assert_eq!(1, index.search("still")?.len(), "the database should be recreated");
+        Ok(())
+    }
+
+    #[test]
+    fn index_builder_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let database = TestDir::new();
+        let path = database.path().join("nested").join("index.sqlite");
+
+        dir.write("apples.md", "Pears")?;
+        dir.write("pears.md", "Pears and more pears, with pears")?;
+
+        let mut index = IndexBuilder::new()
+            .collection(Box::new(dir.path().to_path_buf()))
+            .path(&path)
+            .title_boost(false)
+            .build()?;
+        index.refresh()?;
+
+        assert!(path.exists(), "database should be created at the given path");
+        let results = index.search("pears")?;
+        let uris: Vec<&str> = results.entries().iter().map(Entry::uri).collect();
+        assert_eq!(vec![dir.url_for("pears.md").as_str(), dir.url_for("apples.md").as_str()], uris);
+
+        let mut index = IndexBuilder::new()
+            .collection(Box::new(dir.path().to_path_buf()))
+            .tokenizer("trigram")
+            .build()?;
+        index.refresh()?;
+        assert_eq!(2, index.search("ear")?.len(), "trigram tokenizer matches substrings");
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_index_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        assert_eq!(0, index.size());
+
+        dir.write_with_modified("document.md", "Initial document", at(1))?;
+        index.refresh()?;
 
         assert_eq!(1, index.size());
         assert_eq!(1, index.search("Initial")?.len(), "indexed document should be found");
         assert_eq!(0, index.search("Unknown")?.len(), "unknown document should not be found");
 
-        dir.write("document.md", "Updated document")?;
+        dir.write_with_modified("document.md", "Updated document", at(2))?;
+        index.refresh()?;
+
+        assert_eq!(1, index.size());
+        assert_eq!(0, index.search("Initial")?.len(), "original version should not be found");
+        assert_eq!(1, index.search("Updated")?.len(), "updated version should be found");
+
+        dir.delete("document.md")?;
+        index.refresh()?;
+
+        assert_eq!(0, index.size());
+        assert_eq!(0, index.search("Updated")?.len(), "updated version should no longer be found");
+
+        dir.write("one.md", "One")?;
+        dir.write("two.md", "Two")?;
+        dir.write("three.md", "Three")?;
+        index.refresh()?;
+
+        assert_eq!(3, index.size());
+        assert_eq!(1, index.search("One")?.len(), "all documents should be searchable");
+        assert_eq!(1, index.search("Two")?.len(), "all documents should be searchable");
+        assert_eq!(1, index.search("Three")?.len(), "all documents should be searchable");
+        Ok(())
+    }
+
+    #[test]
+    fn size_by_collection_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let first = TestDir::new();
+        let second = TestDir::new();
+        let mut index = Index::open_in_memory(vec![
+            Box::new(first.path().to_path_buf()),
+            Box::new(second.path().to_path_buf()),
+        ]);
+
+        first.write("one.md", "One")?;
+        first.write("two.md", "Two")?;
+        index.refresh()?;
+
+        assert_eq!(
+            vec![(first.path().to_path_buf().name(), 2), (second.path().to_path_buf().name(), 0)],
+            index.size_by_collection()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refresh_symlinked_duplicate_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let first = TestDir::new();
+        let second = TestDir::new();
+        let mut index = Index::open_in_memory(vec![
+            Box::new(first.path().to_path_buf()),
+            Box::new(second.path().to_path_buf()),
+        ]);
+
+        let original = first.write("original.md", "Duplicated document")?;
+        std::os::unix::fs::symlink(&original, second.path().join("link.md"))?;
+        std::os::unix::fs::symlink(&original, first.path().join("same-vault-link.md"))?;
+        index.refresh()?;
+
+        assert_eq!(1, index.size(), "the same file should only be indexed once");
+        let results = index.search("duplicated")?;
+        assert_eq!(1, results.len());
+        assert_eq!(first.url_for("original.md"), Url::parse(results.entries()[0].uri())?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refresh_follow_symlinks_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let vault = TestDir::new();
+        let shared = TestDir::new();
+        shared.write("shared.md", "Symlinked folder note")?;
+        let note = shared.write("linked.md", "Symlinked file note")?;
+        vault.write("note.md", "Vault note")?;
+        std::os::unix::fs::symlink(shared.path(), vault.path().join("shared"))?;
+        std::os::unix::fs::symlink(&note, vault.path().join("linked.md"))?;
+        std::os::unix::fs::symlink(vault.path(), vault.path().join("loop"))?;
+
+        let directory = |follow_links| Directory {
+            path: vault.path().to_path_buf(),
+            dialect: DialectKind::default(),
+            extensions: collection::extensions(&[]),
+            follow_links,
+        };
+
+        let mut index = Index::open_in_memory(vec![Box::new(directory(false))]);
+        index.refresh()?;
+        assert_eq!(2, index.size(), "symlinked folders shouldn't be walked by default");
+        assert_eq!(0, index.search("folder")?.len());
+
+        let mut index = Index::open_in_memory(vec![Box::new(directory(true))]);
+        index.refresh()?;
+        assert_eq!(3, index.size(), "each document should be indexed once, despite the loop");
+        let results = index.search("folder")?;
+        assert_eq!(shared.url_for("shared.md"), Url::parse(results.entries()[0].uri())?);
+        assert_eq!(1, index.search("file")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_refresh_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let database = TestDir::new();
+        let path = database.path().join("index.sqlite");
+        for n in 0..20 {
+            dir.write(&format!("document-{n}.md"), format!("Document {n}"))?;
+        }
+
+        let workers: Vec<_> = (0..2)
+            .map(|worker| {
+                let root = dir.path().to_path_buf();
+                let path = path.clone();
+                std::thread::spawn(move || -> Result<(), String> {
+                    let mut index = Index::open_from_file(vec![Box::new(root.clone())], &path)
+                        .map_err(|e| e.to_string())?;
+                    for n in 0..10 {
+                        std::fs::write(root.join(format!("worker-{worker}-{n}.md")), "Worker")
+                            .map_err(|e| e.to_string())?;
+                        index.refresh().map_err(|e| e.to_string())?;
+                        index.search("document").map_err(|e| e.to_string())?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().expect("worker panicked")?;
+        }
+
+        let mut index = Index::open_from_file(vec![Box::new(dir.path().to_path_buf())], &path)?;
+        index.refresh()?;
+        assert_eq!(40, index.size());
+        assert_eq!(20, index.search("worker")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_extensions_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        dir.write("note.md", "Markdown note")?;
+        dir.write("long.markdown", "Markdown long")?;
+        dir.write("Upper.MD", "Markdown upper")?;
+        dir.write("component.mdx", "Markdown <Chart data={[1, 2]} />\n\nexport const meta = {}")?;
+        dir.write("notes.txt", "Markdown text")?;
+
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        index.refresh()?;
+        assert_eq!(3, index.search("markdown")?.len());
+
+        let vault = Vault {
+            id: "id".to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            name: String::new(),
+            dialect: DialectKind::default(),
+            extensions: vec!["md".to_string(), "mdx".to_string()],
+            follow_links: false,
+        };
+        let mut index = Index::open_in_memory(vec![Box::new(vault)]);
+        index.refresh()?;
+        let results = index.search("markdown")?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["Upper", "component", "note"], sorted(titles));
+
+        let directory = dir.path().to_string_lossy().parse()?;
+        let mut index = IndexBuilder::new().directory(directory).extension(".mdx").build()?;
+        index.refresh()?;
+        assert_eq!(4, index.search("markdown")?.len(), "directories use the builder's extensions");
+        let component = dir.path().canonicalize()?.join("component.mdx");
+        assert!(index.uri_for(&component).is_some());
+
+        let path = Box::new(dir.path().to_path_buf());
+        let mut index = IndexBuilder::new().collection(path).extension("mdx").build()?;
+        index.refresh()?;
+        assert_eq!(4, index.search("markdown")?.len(), "so do paths");
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_directory_dialect_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let obsidian = TestDir::new();
+        let github = TestDir::new();
+        obsidian.write("note.md", "Linking to [[target]]")?;
+        github.write("readme.md", "Linking to [[target]]")?;
+
+        let mut index = Index::open_in_memory(vec![
+            Box::new(Directory {
+                path: obsidian.path().to_path_buf(),
+                dialect: DialectKind::Obsidian,
+                extensions: collection::extensions(&[]),
+                follow_links: false,
+            }),
+            Box::new(Directory {
+                path: github.path().to_path_buf(),
+                dialect: DialectKind::CommonMark,
+                extensions: collection::extensions(&[]),
+                follow_links: false,
+            }),
+        ]);
+        index.refresh()?;
+
+        let markdown = |dir: &TestDir, name: &str| -> Result<String, rusqlite::Error> {
+            index.connection.query_row(
+                "SELECT markdown FROM documents WHERE uri = ?1",
+                [dir.url_for(name)],
+                |row| row.get(0),
+            )
+        };
+        assert!(markdown(&obsidian, "note.md")?.contains("(obsidian://open?path=target)"));
+        assert!(!markdown(&github, "readme.md")?.contains("obsidian://"));
+        Ok(())
+    }
+
+    #[test]
+    fn directory_from_str_tests() {
+        let directory = |path: &str, dialect| Directory {
+            path: PathBuf::from(path),
+            dialect,
+            extensions: collection::extensions(&[]),
+            follow_links: false,
+        };
+
+        assert_eq!(Ok(directory("notes", DialectKind::Obsidian)), "notes".parse());
+        assert_eq!(Ok(directory("notes", DialectKind::CommonMark)), "notes:commonmark".parse());
+        assert_eq!(Ok(directory("a:b", DialectKind::Obsidian)), "a:b".parse());
+        assert_eq!(Ok(directory("C:/notes", DialectKind::Obsidian)), "C:/notes:obsidian".parse());
+    }
+
+    fn sorted<T: Ord>(mut items: Vec<T>) -> Vec<T> {
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn reset_and_reindex_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("document.md", "Document")?;
+        index.refresh()?;
+        assert_eq!(1, index.size());
+
+        index.reset()?;
+        assert_eq!(0, index.size(), "reset should only clear the index");
+        assert_eq!(0, index.search("Document")?.len());
+
+        index.reindex()?;
+        assert_eq!(1, index.size(), "reindex should rebuild the index");
+        assert_eq!(1, index.search("Document")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn index_document_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![]);
+        let document = Obsidian::document(dir.write("single.md", "Single document")?);
+        let config = IndexConfig::default();
+
+        let tx = index.connection.transaction()?;
+        Index::index_document(&tx, "test", None, &document, &config, &Utc::now())?;
+        Index::index_document(&tx, "test", None, &document, &config, &Utc::now())?;
+        tx.commit()?;
+
+        assert_eq!(1, index.size(), "indexing twice should update the same document");
+        assert_eq!(1, index.search("Single")?.len());
+
+        let tx = index.connection.transaction()?;
+        Index::remove_document(&tx, document.uri().as_str(), &config)?;
+        tx.commit()?;
+
+        assert_eq!(0, index.size());
+        assert_eq!(0, index.search("Single")?.len(), "removed document should not be found");
+        Ok(())
+    }
+
+    #[test]
+    fn add_path_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("existing.md", "Existing")?;
+        index.refresh()?;
+        index.connection.execute("UPDATE documents SET markdown = 'Untouched'", ())?;
+
+        let path = dir.write("added.md", "Added [[Existing]]")?;
+        let id = index.add_path(&path)?;
+        assert_eq!(2, index.size());
+        assert_eq!(dir.url_for("added.md").as_str(), index.search("added")?.entries()[0].uri());
+        let untouched: i64 = index.connection.query_row(
+            "SELECT count(*) FROM documents WHERE markdown = 'Untouched'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(1, untouched, "other documents should be left alone");
+
+        dir.write("added.md", "Changed")?;
+        assert_eq!(id, index.add_path(&path)?, "re-adding should update the same document");
+        assert_eq!(1, index.search("changed")?.len());
+
+        assert!(index.add_path(&dir.path().join("missing.md")).is_err());
+        let outside = TestDir::new();
+        assert!(index.add_path(&outside.write("outside.md", "Outside")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remove_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let collection = Box::new(dir.path().to_path_buf());
+        let mut index = IndexBuilder::new().collection(collection).substring_index(true).build()?;
+
+        let path = dir.write("doomed.md", "---\nstatus: doomed\n---\nDoomed #tag [[Other]]")?;
+        dir.write("kept.md", "Kept")?;
+        index.refresh()?;
+
+        let uri = index.uri_for(&path).expect("path is in the collection");
+        assert_eq!(Url::parse(index.search("doomed")?.entries()[0].uri())?, uri);
+        assert!(index.remove(uri.as_str())?);
+        assert!(!index.remove(uri.as_str())?, "already removed");
+
+        assert!(index.search("doomed")?.is_empty(), "removed document should not be found");
+        assert_eq!(1, index.size());
+        let tables = [
+            "word_index",
+            "text_trigrams",
+            "title_trigrams",
+            "document_tags",
+            "document_metadata",
+            "document_links",
+        ];
+        for table in tables {
+            let rows: i64 = index.connection.query_row(
+                &format!("SELECT count(*) FROM {table} WHERE document_id NOT IN (SELECT id FROM documents)"),
+                [],
+                |row| row.get(0),
+            )?;
+            assert_eq!(0, rows, "{table} rows should be removed");
+        }
+
+        assert_eq!(None, index.uri_for(Path::new("/elsewhere/note.md")));
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_path_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("keep.md", "Keep")?;
+        let path = dir.write_with_modified("document.md", "Initial", at(1))?.canonicalize()?;
+        index.refresh()?;
+        assert_eq!(2, index.size());
+
+        dir.write_with_modified("document.md", "Updated", at(2))?;
+        assert!(index.refresh_path(&path)?);
+        assert_eq!(2, index.size());
+        assert_eq!(0, index.search("Initial")?.len(), "original version should not be found");
+        assert_eq!(1, index.search("Updated")?.len(), "updated version should be found");
+
+        dir.delete("document.md")?;
+        assert!(index.refresh_path(&path)?);
+        assert_eq!(1, index.size());
+        assert_eq!(0, index.search("Updated")?.len(), "deleted document should not be found");
+        assert_eq!(1, index.search("Keep")?.len(), "other documents should be untouched");
+
+        assert!(!index.refresh_path(&path.with_extension("txt"))?, "non-markdown ignored");
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_invalid_utf8_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("latin1.md", b"Caf\xe9 cr\xe8me [caf\xe9](caf\xe9.md)")?;
+        index.refresh()?;
+
+        assert_eq!(1, index.size());
+        let results = index.search("cr")?;
+        assert_eq!(1, results.len(), "document with invalid UTF-8 should be indexed");
+        assert!(
+            results.entries()[0].markdown.contains('\u{FFFD}'),
+            "invalid bytes should be replaced"
+        );
+        let stored: (i64, String) =
+            index.connection.query_row("SELECT length, checksum FROM documents", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        assert_eq!(26, stored.0, "length should count the bytes on disk");
+        assert_eq!(source::hex_digest(b"Caf\xe9 cr\xe8me [caf\xe9](caf\xe9.md)"), stored.1);
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_changes_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write_with_modified("kept.md", "Kept", at(1))?;
+        dir.write_with_modified("changed.md", "Original", at(1))?;
+        dir.write_with_modified("deleted.md", "Deleted", at(1))?;
+
+        let added = Changes { added: 3, ..Default::default() };
+        assert_eq!(added, index.refresh_changes(true)?, "dry run");
+        assert_eq!(0, index.size(), "a dry run shouldn't change the index");
+        assert_eq!(added, index.refresh_changes(false)?);
+        assert_eq!(3, index.size());
+
+        dir.write_with_modified("changed.md", "Changed", at(2))?;
+        dir.delete("deleted.md")?;
+        dir.write("new.md", "New")?;
+        let changes = Changes { added: 1, updated: 1, removed: 1, unchanged: 1, skipped: 0 };
+        assert_eq!(changes, index.refresh_changes(true)?, "dry run");
+        assert_eq!(1, index.search("deleted")?.len(), "a dry run shouldn't change the index");
+        assert!(index.search("new")?.is_empty(), "a dry run shouldn't change the index");
+        assert_eq!(changes, index.refresh_changes(false)?);
+        assert!(index.search("deleted")?.is_empty());
+
+        let rebuilt = Changes { added: 3, ..Default::default() };
+        assert_eq!(rebuilt, index.reindex_changes(true)?, "dry run");
+        assert_eq!(3, index.size(), "a dry run shouldn't change the index");
+        assert_eq!(Changes { unchanged: 3, ..Default::default() }, index.refresh_changes(true)?);
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_at_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write_with_modified("kept.md", "Kept", at(1))?;
+        dir.write_with_modified("deleted.md", "Deleted", at(1))?;
+
+        assert_eq!(Changes { added: 2, ..Default::default() }, index.refresh_at(at(10).into())?);
+        let last_refresh: DateTime<Utc> =
+            index
+                .connection
+                .query_row("SELECT last_refresh FROM application", [], |row| row.get(0))?;
+        assert_eq!(DateTime::<Utc>::from(at(10)), last_refresh);
+
+        // Only documents last seen before the given time are stale, whatever the real time is.
+        dir.delete("deleted.md")?;
+        let changes = Changes { removed: 1, unchanged: 1, ..Default::default() };
+        assert_eq!(changes, index.refresh_at(at(11).into())?);
+        assert_eq!(1, index.size());
+        Ok(())
+    }
+
+    #[test]
+    fn find_by_uri_and_id_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let index = Index::from_documents(vec![NamedSource::new("Recipe", "Flour and eggs")])?;
+        let found = index.search("flour")?.entries.remove(0);
+
+        let by_uri = index.find_by_uri(found.uri())?.unwrap();
+        assert_eq!("Recipe", by_uri.title());
+        assert_eq!("Flour and eggs\n", by_uri.markdown());
+        assert_eq!(Some(source::hex_digest(b"Flour and eggs").as_str()), by_uri.checksum());
+        assert_eq!(Some(by_uri), index.find_by_id(found.id().unwrap())?);
+        assert_eq!(None, index.find_by_uri("file:///missing.md")?);
+        assert_eq!(None, index.find_by_id(-1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_front_matter_only_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        let body = "Notes on [[Gardening]]\n\n```rust\nfn main() {}\n```\n";
+        dir.write_with_modified("note.md", format!("---\ntitle: Spring\n---\n{body}"), at(1))?;
+        index.refresh()?;
+
+        dir.write_with_modified(
+            "note.md",
+            format!("---\ntitle: Autumn\ntags: [seasons]\n---\n{body}"),
+            at(2),
+        )?;
+        assert_eq!(Changes { updated: 1, ..Default::default() }, index.refresh_changes(false)?);
+
+        let titles = |index: &Index, query: &str| -> Vec<String> {
+            let results = index.search(query).unwrap();
+            results.entries().iter().map(|entry| entry.title().to_string()).collect()
+        };
+        assert_eq!(vec!["Autumn"], titles(&index, "title:autumn"));
+        assert!(titles(&index, "spring").is_empty(), "the old title should be gone");
+        assert_eq!(vec!["Autumn"], titles(&index, "notes #seasons"), "the body is still indexed");
+        let options = SearchOptions { language: Some("rust".into()), ..Default::default() };
+        assert_eq!(1, index.search_with("", &options)?.len(), "code languages are kept");
+        let results = index.search("autumn")?;
+        assert!(results.entries()[0].markdown().starts_with("Notes on [Gardening]"));
+        assert!(index.broken_links()?.iter().any(|links| links.pages == ["Gardening"]));
+
+        dir.write_with_modified("note.md", "---\ntitle: Autumn\n---\nRewritten", at(3))?;
+        index.refresh()?;
+        assert_eq!(vec!["Autumn"], titles(&index, "rewritten"));
+        assert!(titles(&index, "notes").is_empty(), "a body change is indexed in full");
+        Ok(())
+    }
+
+    #[test]
+    fn schema_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let schema = serde_json::to_value(schemars::schema_for!(SearchResults))?;
+        assert_eq!("array", schema["type"]);
+        assert_eq!("#/definitions/Entry", schema["items"]["$ref"]);
+
+        // Every field that can be output is described, and nothing else.
+        let properties = schema["definitions"]["Entry"]["properties"].as_object().unwrap();
+        let described: Vec<&str> = properties.keys().map(String::as_str).collect();
+        let names: Vec<&str> = Field::ALL.iter().map(Field::name).collect();
+        assert_eq!(names, described);
+
+        let mut results = Index::from_documents(vec![NamedSource::new("A", "[[B]]")])?
+            .search_with("", &SearchOptions { with_links: true, ..Default::default() })?;
+        let entry = serde_json::to_value(results.entries.remove(0))?;
+        let serialized: Vec<&str> = entry.as_object().unwrap().keys().map(String::as_str).collect();
+        let searched: Vec<&str> =
+            names.iter().copied().filter(|name| *name != "checksum").collect();
+        assert_eq!(searched, serialized, "checksums are only read when finding by uri or id");
+        Ok(())
+    }
+
+    #[test]
+    fn entry_id_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write_with_modified("first.md", "Apple", at(1))?;
+        dir.write_with_modified("second.md", "Apple pie", at(1))?;
+        index.refresh()?;
+
+        let ids = |index: &Index| -> Result<Vec<(String, i64)>, Box<dyn std::error::Error>> {
+            let results = index.search("apple")?;
+            let entries = results.entries().iter();
+            Ok(entries.map(|entry| (entry.title().to_string(), entry.id().unwrap())).collect())
+        };
+        let before = ids(&index)?;
+        assert_eq!(2, before.len());
+        assert_ne!(before[0].1, before[1].1);
+
+        dir.write_with_modified("second.md", "Apple crumble", at(2))?;
+        dir.write("third.md", "Apple tart")?;
+        index.refresh()?;
+        let after = ids(&index)?;
+        assert!(before.iter().all(|entry| after.contains(entry)), "ids stay the same on update");
+
+        let json = serde_json::to_value(Projection {
+            entry: &index.search("crumble")?.entries[0],
+            fields: &[Field::Id, Field::Title],
+        })?;
+        assert_eq!(serde_json::json!({"id": before[1].1, "title": "second"}), json);
+        Ok(())
+    }
+
+    /// A collection for refresh tests, listing whatever `list` returns each time it's read: a
+    /// source for each document, or the error that stopped it being listed. Like a folder, it
+    /// lists documents one at a time, so fails the test if refresh asks for them all at once.
+    struct Listing<F>(F);
+
+    impl<F: Fn() -> Vec<std::io::Result<Box<dyn Source>>>> Collection for Listing<F> {
+        fn documents(&self) -> Vec<Document<'_>> {
+            unreachable!("refresh should read documents one at a time")
+        }
+
+        fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+            let listed = (self.0)().into_iter();
+            Ok(Box::new(
+                listed.map(|source| source.map(|source| Document { source, ..Default::default() })),
+            ))
+        }
+
+        fn name(&self) -> String {
+            "listing".to_string()
+        }
+    }
+
+    /// A note to list in a `Listing`, modified at a fixed time so it's unchanged between
+    /// refreshes.
+    fn listed(title: &str, content: &str) -> std::io::Result<Box<dyn Source>> {
+        Ok(Box::new(NamedSource::new(title, content).modified(at(1).into())))
+    }
+
+    #[test]
+    fn refresh_documents_iter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let streamed =
+            Listing(|| (0..3).map(|n| listed("Note", &format!("Streamed {n}"))).collect());
+        let mut index = Index::open_in_memory(vec![Box::new(streamed)]);
+        index.refresh()?;
+        assert_eq!(3, index.search("streamed")?.len());
+
+        let dir = TestDir::new();
+        dir.write("one.md", "One")?;
+        dir.write("nested/two.md", "Two")?;
+        let uris = |documents: Vec<Document>| -> Vec<Url> {
+            let mut uris: Vec<Url> = documents.iter().map(Document::uri).collect();
+            uris.sort();
+            uris
+        };
+        let collection = dir.path().to_path_buf();
+        assert_eq!(
+            uris(collection.documents()),
+            uris(collection.documents_iter()?.collect::<Result<_, _>>()?),
+            "both should list the same documents"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_streamed_stale_documents_tests() -> Result<(), Box<dyn std::error::Error>> {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let contents = Rc::new(RefCell::new(vec!["First", "Second", "Third"]));
+        let listing = contents.clone();
+        let shrinking = Listing(move || {
+            listing.borrow().iter().map(|content| listed("Note", content)).collect()
+        });
+        let mut index = Index::open_in_memory(vec![Box::new(shrinking)]);
+        index.refresh()?;
+        assert_eq!(3, index.size());
+
+        contents.borrow_mut().retain(|content| *content != "Second");
+        index.refresh()?;
+
+        assert_eq!(2, index.size(), "documents no longer listed should be removed");
+        assert!(index.search("second")?.is_empty());
+        assert_eq!(1, index.search("first")?.len(), "unchanged documents should be kept");
+        assert_eq!(1, index.search("third")?.len(), "unchanged documents should be kept");
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_listing_error_tests() -> Result<(), Box<dyn std::error::Error>> {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let unreadable = Rc::new(Cell::new(false));
+        let listing = unreadable.clone();
+        let collection = Listing(move || {
+            let second = match listing.get() {
+                true => Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+                false => listed("Note", "Second"),
+            };
+            vec![listed("Note", "First"), second]
+        });
+        let mut index = Index::open_in_memory(vec![Box::new(collection)]);
+        index.refresh()?;
+        assert_eq!(2, index.size());
+
+        unreadable.set(true);
+        index.refresh()?;
+
+        assert_eq!(2, index.size(), "documents that couldn't be listed should be kept");
+        assert_eq!(1, index.search("second")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_failing_collection_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let good = TestDir::new();
+        let bad = TestDir::new();
+        good.write("good.md", "Good document")?;
+        bad.write("bad.md", "Bad document")?;
+        let mut index = Index::open_in_memory(vec![
+            Box::new(bad.path().to_path_buf()),
+            Box::new(good.path().to_path_buf()),
+        ]);
+        index.refresh()?;
+        assert_eq!(2, index.size());
+
+        let bad_path = bad.path().to_path_buf();
+        drop(bad);
+        good.write("new.md", "New document")?;
+        index.refresh()?;
+
+        assert_eq!(1, index.search("new")?.len(), "other collections should still refresh");
+        assert_eq!(1, index.search("bad")?.len(), "failed collection should keep its documents");
+
+        let mut index = Index::open_in_memory(vec![
+            Box::new(bad_path.join("missing")),
+            Box::new(good.path().to_path_buf()),
+        ]);
+        index.refresh()?;
+        assert_eq!(2, index.size(), "an invalid path shouldn't stop others being indexed");
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_unreadable_document_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let gone = dir.path().join("gone.md");
+        let vanishing = Listing(move || {
+            vec![Ok(Box::new(gone.clone()) as Box<dyn Source>), listed("Plain", "A plain note")]
+        });
+        let mut index = Index::open_in_memory(vec![Box::new(vanishing)]);
+        let changes = index.refresh_changes(false)?;
+
+        assert_eq!(Changes { added: 1, skipped: 1, ..Default::default() }, changes);
+        assert_eq!(1, index.search("plain")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_panicking_document_tests() -> Result<(), Box<dyn std::error::Error>> {
+        struct Panicking;
+
+        impl Source for Panicking {
+            fn read(&self) -> String {
+                panic!("unparseable document")
+            }
+
+            fn url(&self) -> Url {
+                Url::parse("memory:panicking").unwrap()
+            }
+        }
+
+        let mixed = Listing(|| {
+            vec![
+                Ok(Box::new(Panicking) as Box<dyn Source>),
+                listed("Brackets", "[[[[a]]|[[b|]]]] [[|]] [[[x]]"),
+                listed("Plain", "A plain note"),
+            ]
+        });
+        let mut index = Index::open_in_memory(vec![Box::new(mixed)]);
+        index.refresh()?;
+
+        assert_eq!(2, index.size(), "panicking document should be skipped");
+        assert_eq!(1, index.search("plain")?.len());
+        assert_eq!(1, index.search("brackets")?.len(), "malformed brackets should index");
+        Ok(())
+    }
+
+    #[test]
+    fn search_result_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("root.md", "Root")?;
+        dir.write("folder/child.md", "Child")?;
+        index.refresh()?;
+
+        assert_eq!(
+            dir.url_for("root.md"),
+            Url::parse(index.search("Root")?.entries()[0].uri()).unwrap()
+        );
+        assert_eq!(
+            dir.url_for("folder/child.md"),
+            Url::parse(index.search("Child")?.entries()[0].uri()).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn search_result_path_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        let path = dir.write("folder/with space.md", "Spaced")?;
+        index.refresh()?;
+
+        let results = index.search("Spaced")?;
+        assert_eq!(path.canonicalize()?.to_str(), results.entries()[0].path.as_deref());
+        Ok(())
+    }
+
+    #[test]
+    fn search_document_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("plain.md", "Very SIMPLE document")?;
+        index.refresh()?;
+
+        for query in ["very", "VeRy", "simple", "SIMPLE", "document"] {
+            let results = index.search(query);
+            assert_eq!(1, results?.len(), "match word");
+        }
+
+        for query in ["simple document", "very simple", "document simple very"] {
+            let results = index.search(query);
+            assert_eq!(1, results?.len(), "match multiple words");
+        }
+
+        for query in ["missing", "simple missing document", "very simple missing"] {
+            let results = index.search(query);
+            assert_eq!(0, results?.len(), "don't match missing word");
+        }
+
+        for query in ["v", "si", "doc"] {
+            let results = index.search(query);
+            assert_eq!(1, results?.len(), "match word prefixes ({query})");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn search_inline_tags_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("doc.md", "Document with #inline tags #after")?;
+        index.refresh()?;
+
+        for query in ["#inline", "#after"] {
+            let results = index.search(query);
+            assert_eq!(1, results?.len(), "match qualified tags");
+        }
+
+        for query in ["inline", "after"] {
+            let results = index.search(query);
+            assert_eq!(0, results?.len(), "don't match unqualified tag");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_front_matter_tags_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        let content = indoc! {"
+            ---
+            tags: first, second
+            ---
+            Document with front matter tags
+        "};
+
+        dir.write("doc.md", content)?;
+        index.refresh()?;
+
+        for query in ["#first", "#second"] {
+            let results = index.search(query);
+            assert_eq!(1, results?.len(), "match qualified tags");
+        }
+
+        for query in ["first", "second"] {
+            let results = index.search(query);
+            assert_eq!(0, results?.len(), "don't match unqualified tag");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_nested_tags_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("a.md", "Tagged #a")?;
+        dir.write("ab.md", "Tagged #a/b")?;
+        dir.write("abc.md", "---\ntags: a/b/c\n---\nTagged in front matter")?;
+        dir.write("other.md", "Tagged #other/a/b")?;
+        index.refresh()?;
+
+        assert_eq!(3, index.search("#a")?.len(), "parent tag matches all descendants");
+        assert_eq!(2, index.search("#a/b")?.len(), "nested tag matches its descendants");
+        assert_eq!(1, index.search("#a/b/c")?.len(), "leaf tag only matches itself");
+        assert_eq!(0, index.search("#b")?.len(), "child tags aren't tags on their own");
+        assert_eq!(1, index.search("#a/b front")?.len(), "tags combine with words");
+        Ok(())
+    }
+
+    #[test]
+    fn search_tags_case_insensitively_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write_with_modified("upper.md", "---\ntags: Project\n---\nFront matter tag", at(1))?;
+        dir.write_with_modified("lower.md", "Inline #project tag", at(3))?;
+        dir.write_with_modified("plural.md", "Inline #projects tag", at(2))?;
+        index.refresh()?;
+
+        for query in ["#project", "#Project", "#PROJECT"] {
+            assert_eq!(2, index.search(query)?.len(), "match tags in any case ({query})");
+        }
+        let results = index.search("#project")?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["lower", "upper"], titles, "whole tags only, most recently modified first");
+        Ok(())
+    }
+
+    #[test]
+    fn search_date_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        let before = Utc::now() - chrono::Duration::days(1);
+        dir.write_with_modified("old.md", "Old document", at(1))?;
+        dir.write_with_modified("new.md", "New document", at(3))?;
+        index.refresh()?;
+
+        let between = DateTime::<Utc>::from(at(2));
+        let modified = SearchOptions { modified_since: Some(between), ..Default::default() };
+        assert_eq!(1, index.search_with("document", &modified)?.len(), "combined with a query");
+        assert_eq!(1, index.search_with("", &modified)?.len(), "without a query");
+        assert_eq!(0, index.search_with("old", &modified)?.len());
+
+        // Creation times can't be set, so only check either side of when the files were written
+        let created = SearchOptions { created_since: Some(before), ..Default::default() };
+        assert_eq!(2, index.search_with("document", &created)?.len());
+        let created = SearchOptions { created_since: Some(Utc::now()), ..Default::default() };
+        assert_eq!(0, index.search_with("document", &created)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn search_front_matter_values_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("doc.md", "---\nsecret: hunter2\n---\nDocument body")?;
+        dir.write("unterminated.md", "---\nsecret: hunter2\n---")?;
+        index.refresh()?;
+
+        assert_eq!(0, index.search("hunter2")?.len(), "front matter values aren't indexed");
+        assert_eq!(0, index.search("secret")?.len(), "front matter keys aren't indexed");
+        assert_eq!(1, index.search("body")?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn search_since_previous_refresh_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        // Files are created at the real time, so the refreshes run in the days after it.
+        let day = |days| Utc::now() + chrono::Duration::days(days);
+        let first = day(1);
+
+        assert_eq!(None, index.previous_refresh()?);
+        dir.write_with_modified("old.md", "Old", at(1))?;
+        index.refresh_at(first)?;
+        assert_eq!(None, index.previous_refresh()?, "there's only been one refresh");
+
+        dir.write_with_modified("new.md", "New", day(2).into())?;
+        index.refresh_at(day(3))?;
+        let previous = index.previous_refresh()?.expect("the first refresh is recorded");
+        assert_eq!(first, previous);
+
+        let changed = SearchOptions { changed_since: Some(previous), ..Default::default() };
+        let results = index.search_with("", &changed)?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["new"], titles);
+
+        index.refresh_at(day(4))?;
+        let changed = SearchOptions { changed_since: index.previous_refresh()?, ..changed };
+        assert!(index.search_with("", &changed)?.is_empty(), "nothing changed since");
+        Ok(())
+    }
+
+    #[test]
+    fn search_published_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Published", "---\npublish: true\n---\nPost"),
+            NamedSource::new("Unpublished", "---\npublish: false\n---\nPost"),
+            NamedSource::new("Draft", "---\ndraft: true\n---\nPost"),
+            NamedSource::new("Unmarked", "Post"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let titles = |options: &SearchOptions| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search_with("post", options)?;
+            let mut titles: Vec<String> =
+                results.entries().iter().map(|entry| entry.title().to_string()).collect();
+            titles.sort();
+            Ok(titles)
+        };
+
+        assert_eq!(4, titles(&SearchOptions::default())?.len());
+        let published = SearchOptions { published_only: true, ..Default::default() };
+        assert_eq!(vec!["Published", "Unmarked"], titles(&published)?);
+        let strict = SearchOptions { unmarked_as_drafts: true, ..published };
+        assert_eq!(vec!["Published"], titles(&strict)?);
+        Ok(())
+    }
+
+    #[test]
+    fn search_folder_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write("top.md", "Plan")?;
+        dir.write("Projects/projects.md", "Plan")?;
+        dir.write("Projects/Acme/acme.md", "Plan")?;
+        dir.write("Projects/Acme/Research/research.md", "Plan")?;
+        dir.write("Projects/Acme Two/acme-two.md", "Plan")?;
+        index.refresh()?;
+
+        let titles = |folder: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let options = SearchOptions { folder: Some(folder.into()), ..Default::default() };
+            let results = index.search_with("plan", &options)?;
+            let mut titles: Vec<String> =
+                results.entries().iter().map(|entry| entry.title().to_string()).collect();
+            titles.sort();
+            Ok(titles)
+        };
+
+        assert_eq!(vec!["acme", "acme-two", "projects", "research"], titles("Projects")?);
+        assert_eq!(vec!["acme", "research"], titles("Projects/Acme/")?);
+        assert_eq!(vec!["research"], titles("Projects/Acme/Research")?);
+        assert!(titles("Projects/Ac")?.is_empty(), "only whole folders should match");
+        assert_eq!(5, titles("")?.len(), "an empty folder should match everything");
+        Ok(())
+    }
+
+    #[test]
+    fn group_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write("Projects/alpha.md", "---\ntype: Project\ntags: [work, area/home]\n---\nPlan")?;
+        dir.write("Projects/beta.md", "---\ntype: Project\n---\nPlan #area/home/garden #area")?;
+        dir.write("gamma.md", "---\ntype: Person\n---\nPlan")?;
+        dir.write("delta.md", "Plan")?;
+        index.refresh()?;
+
+        let results = index.search("plan")?;
+        // Each group as its name and sorted titles, such as `Project: alpha, beta`.
+        let groups = |by| -> Result<Vec<String>, rusqlite::Error> {
+            let groups = index.group(results.entries(), by)?;
+            Ok(groups
+                .into_iter()
+                .map(|group| {
+                    let mut titles: Vec<&str> =
+                        group.entries.iter().map(|entry| entry.title()).collect();
+                    titles.sort();
+                    format!("{}: {}", group.name.as_deref().unwrap_or("-"), titles.join(", "))
+                })
+                .collect())
+        };
+
+        assert_eq!(
+            vec!["Person: gamma", "Project: alpha, beta", "-: delta"],
+            groups(GroupBy::Type)?
+        );
+        assert_eq!(
+            vec!["area/home: alpha", "area/home/garden: beta", "work: alpha", "-: delta, gamma"],
+            groups(GroupBy::Tag)?,
+            "entries appear under each tag, but not under parents of their tags"
+        );
+        assert_eq!(vec!["Projects: alpha, beta", "-: delta, gamma"], groups(GroupBy::Folder)?);
+        assert_eq!(1, groups(GroupBy::Vault)?.len());
+        assert_eq!(Ok(GroupBy::Tag), "tag".parse());
+        assert!("author".parse::<GroupBy>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn search_metadata_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new(
+                "Active",
+                "---\nstatus: active\npriority: 1\nstarred: true\n---\nTask",
+            ),
+            NamedSource::new("Done", "---\nstatus: done\npriority: 2\nstarred: false\n---\nTask"),
+            NamedSource::new("Listed", "---\nstatus: [active, blocked]\n---\nTask"),
+            NamedSource::new("Unset", "Task"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let titles =
+            |metadata: &[(&str, &str)]| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+                let metadata =
+                    metadata.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+                let options = SearchOptions { metadata, ..Default::default() };
+                let results = index.search_with("task", &options)?;
+                let mut titles: Vec<String> =
+                    results.entries().iter().map(|entry| entry.title().to_string()).collect();
+                titles.sort();
+                Ok(titles)
+            };
+
+        assert_eq!(vec!["Active", "Listed"], titles(&[("status", "Active")])?);
+        assert_eq!(vec!["Done"], titles(&[("priority", "2")])?);
+        assert_eq!(vec!["Active"], titles(&[("starred", "true")])?);
+        assert_eq!(vec!["Active"], titles(&[("status", "active"), ("priority", "1")])?);
+        assert!(titles(&[("status", "missing")])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn search_type_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Alice", "---\ntype: Person\n---\nFriend"),
+            NamedSource::new("Bob", "[[type=person]] Friend"),
+            NamedSource::new("Paris", "---\ntype: Place\n---\nFriend"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let options = SearchOptions { doc_type: Some("person".into()), ..Default::default() };
+        let mut titles: Vec<String> = index
+            .search_with("friend", &options)?
+            .entries()
+            .iter()
+            .map(|entry| entry.title().to_string())
+            .collect();
+        titles.sort();
+
+        assert_eq!(vec!["Alice", "Bob"], titles);
+        Ok(())
+    }
+
+    #[test]
+    fn search_vault_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let work = TestDir::new();
+        let home = TestDir::new();
+        let mut index = Index::open_in_memory(vec![
+            Box::new(work.path().to_path_buf()),
+            Box::new(home.path().to_path_buf()),
+        ]);
+
+        work.write("work.md", "Shared document")?;
+        home.write("home.md", "Shared document")?;
+        index.refresh()?;
+
+        let options =
+            SearchOptions { vault: Some(work.path().to_path_buf().name()), ..Default::default() };
+        let results = index.search_with("shared", &options)?;
+        assert_eq!(1, results.len());
+        assert_eq!(work.url_for("work.md"), Url::parse(results.entries()[0].uri())?);
+
+        let options = SearchOptions { vault: Some("Missing".to_string()), ..Default::default() };
+        let error = index.search_with("shared", &options).err().unwrap().to_string();
+        assert!(error.contains("Unknown vault `Missing`"), "{error}");
+        assert!(error.contains(&home.path().to_path_buf().name()), "lists valid names: {error}");
+
+        let vault = |id: &str, dir: &TestDir| Vault {
+            id: id.to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            name: String::new(),
+            dialect: DialectKind::default(),
+            extensions: vec!["md".to_string()],
+            follow_links: false,
+        };
+        let mut index =
+            Index::open_in_memory(vec![Box::new(vault("w1", &work)), Box::new(vault("h2", &home))]);
+        index.refresh()?;
+        let options = SearchOptions { vault: Some("h2".to_string()), ..Default::default() };
+        let results = index.search_with("shared", &options)?;
+        assert_eq!(1, results.len(), "vaults can be chosen by id");
+        assert_eq!("home", results.entries()[0].title());
+        Ok(())
+    }
+
+    #[test]
+    fn search_language_filter_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("rust.md", "Example\n\n```rust\nfn main() {}\n```\n")?;
+        dir.write("ruby.md", "Example\n\n```Ruby\nputs 1\n```\n")?;
+        dir.write("plain.md", "Example\n\n```\nno language\n```\n")?;
+        index.refresh()?;
+
+        let search = |language: &str| {
+            let options =
+                SearchOptions { language: Some(language.to_string()), ..Default::default() };
+            index.search_with("example", &options).map(|results| results.len())
+        };
+        assert_eq!(1, search("rust")?);
+        assert_eq!(1, search("RUBY")?, "languages should match case-insensitively");
+        assert_eq!(0, search("python")?);
+        assert_eq!(1, index.search("main")?.len(), "code should still be indexed as text");
+        Ok(())
+    }
+
+    #[test]
+    fn find_by_title_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("My Note", "First"),
+            NamedSource::new("my note", "Lowercase"),
+            NamedSource::new("My Notes", "Plural"),
+            NamedSource::new("Shared", "---\ntype: person\n---\nA person"),
+            NamedSource::new("Place", "---\ntitle: Shared\ntype: place\n---\nSee [[My Note]]"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+        let find =
+            |title: &str, options: &SearchOptions| index.find_by_title(title, options).unwrap();
+        let title = |title: &str| find(title, &SearchOptions::default()).map(|entry| entry.title);
+
+        assert_eq!(Some("My Note".into()), title("My Note"));
+        assert_eq!(Some("my note".into()), title("my note"));
+        assert_eq!(None, title("My No"), "no prefix matching");
+        assert_eq!(None, title("MY NOTE"), "case must match");
+
+        let places = SearchOptions {
+            doc_type: Some("place".into()),
+            with_links: true,
+            ..Default::default()
+        };
+        let place = find("Shared", &places).unwrap();
+        assert_eq!(Some("place"), place.doc_type.as_deref(), "filters choose between titles");
+        assert_eq!(1, place.links().unwrap_or_default().len(), "links are added when asked for");
+        let people = SearchOptions { doc_type: Some("person".into()), ..Default::default() };
+        assert!(find("My Note", &people).is_none(), "filters apply");
+        Ok(())
+    }
+
+    #[test]
+    fn find_by_alias_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Robert", "---\naliases: [Bob, Bobby Tables]\n---\nRobert"),
+            NamedSource::new("Bob", "---\ntitle: Bob\n---\nThe other Bob"),
+            NamedSource::new("Alice", "---\nalias: Al\n---\nAlice"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
         index.refresh()?;
 
-        assert_eq!(1, index.size());
-        assert_eq!(0, index.search("Initial")?.len(), "original version should not be found");
-        assert_eq!(1, index.search("Updated")?.len(), "updated version should be found");
+        let title = |title: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+            let entry = index.find_by_title(title, &SearchOptions::default())?;
+            Ok(entry.map(|entry| entry.title().to_string()))
+        };
+        assert_eq!(Some("Robert".into()), title("Bobby Tables")?);
+        assert_eq!(Some("Alice".into()), title("Al")?);
+        assert_eq!(Some("Bob".into()), title("Bob")?, "titles take precedence over aliases");
+        assert_eq!(None, title("bobby tables")?, "aliases must match case");
+        assert_eq!(None, title("Tables")?);
+        Ok(())
+    }
 
-        dir.delete("document.md")?;
+    #[test]
+    fn search_fuzzy_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("Markdown notes.md", "")?;
+        dir.write("Markup languages.md", "")?;
+        dir.write("Marked.md", "")?;
+        dir.write("Parting.md", "")?;
+        dir.write("Parsing.md", "")?;
         index.refresh()?;
 
-        assert_eq!(0, index.size());
-        assert_eq!(0, index.search("Updated")?.len(), "updated version should no longer be found");
+        let fuzzy = SearchOptions { fuzzy: true, ..Default::default() };
+        assert_eq!(0, index.search("markdonw")?.len(), "typos don't match without fuzzy");
 
-        dir.write("one.md", "One")?;
-        dir.write("two.md", "Two")?;
-        dir.write("three.md", "Three")?;
+        let results = index.search_with("markdonw", &fuzzy)?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["Markdown notes"], titles);
+
+        let results = index.search_with("pursing", &fuzzy)?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["Parsing", "Parting"], titles, "closest titles come first");
+
+        let results = index.search_with("mark", &fuzzy)?;
+        assert_eq!(3, results.len(), "exact matches are returned without fuzzy matching");
+        Ok(())
+    }
+
+    #[test]
+    fn similar_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Sourdough", "Sourdough needs flour, water and a starter. Knead it."),
+            NamedSource::new("Pizza", "Pizza dough is flour, water and yeast. Knead it well."),
+            NamedSource::new("Kubernetes", "Kubernetes schedules containers onto cluster nodes."),
+            NamedSource::new("Docker", "Docker containers run on cluster nodes. #ops"),
+            NamedSource::new("Grafana", "Grafana dashboards. #ops"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
         index.refresh()?;
 
-        assert_eq!(3, index.size());
-        assert_eq!(1, index.search("One")?.len(), "all documents should be searchable");
-        assert_eq!(1, index.search("Two")?.len(), "all documents should be searchable");
-        assert_eq!(1, index.search("Three")?.len(), "all documents should be searchable");
+        let titles = |results: SearchResults| -> Vec<String> {
+            results.entries().iter().map(|entry| entry.title().to_string()).collect()
+        };
+
+        let results = index.similar("sourdough", &SearchOptions::default())?;
+        assert_eq!(vec!["Pizza"], titles(results), "shared words, excluding itself");
+
+        let results = index.similar("docker", &SearchOptions::default())?;
+        assert_eq!(vec!["Grafana", "Kubernetes"], titles(results), "shared tags come first");
+
+        let limited = SearchOptions { limit: Some(1), ..Default::default() };
+        assert_eq!(vec!["Grafana"], titles(index.similar("docker", &limited)?));
+
+        assert!(index.similar("nothing", &SearchOptions::default()).is_err());
         Ok(())
     }
 
     #[test]
-    fn search_result_tests() -> Result<(), Box<dyn std::error::Error>> {
+    fn search_with_links_tests() -> Result<(), Box<dyn std::error::Error>> {
         let dir = TestDir::new();
         let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
 
-        dir.write("root.md", "Root")?;
-        dir.write("folder/child.md", "Child")?;
+        dir.write("folder/Target Page.md", "---\naliases: [Aim, Other]\n---\nThe target")?;
+        dir.write("Other.md", "Named the same as an alias")?;
+        dir.write(
+            "source.md",
+            "Links to [[Target Page]], [[folder/target page.md#Heading|alias]], [[Missing Page]] \
+             and [elsewhere](https://example.com/), [[aim]] [[Other]]",
+        )?;
         index.refresh()?;
 
+        let results = index.search("links")?;
+        assert_eq!(None, results.entries()[0].links(), "links are only included when asked for");
+
+        let with_links = SearchOptions { with_links: true, ..Default::default() };
+        let results = index.search_with("links", &with_links)?;
+        let target = index.search("target")?.entries()[0].uri().to_string();
+        let other = dir.url_for("Other.md").to_string();
         assert_eq!(
-            dir.url_for("root.md"),
-            Url::parse(index.search("Root")?.entries()[0].uri()).unwrap()
+            Some(
+                &[
+                    target.clone(),
+                    target.clone(),
+                    "Missing Page".into(),
+                    "https://example.com/".into(),
+                    target,
+                    other
+                ][..]
+            ),
+            results.entries()[0].links(),
+            "aliases resolve, but file titles take precedence"
         );
+
+        dir.delete("source.md")?;
+        index.refresh()?;
+        let links: i64 =
+            index
+                .connection
+                .query_row("SELECT count(*) FROM document_links", [], |row| row.get(0))?;
+        assert_eq!(0, links, "links of removed documents should be deleted");
+        Ok(())
+    }
+
+    #[test]
+    fn search_fields_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![NamedSource::new("Projected", "Projected body")];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let fields = vec![Field::Url, Field::Title];
+        let options = SearchOptions { fields: Some(fields.clone()), ..Default::default() };
+        let results = index.search_with("projected", &options)?;
+        let entry = &results.entries()[0];
+        assert_eq!("", entry.markdown, "markdown isn't read unless it's a chosen field");
+
+        let json = serde_json::to_value(Projection { entry, fields: &fields })?;
         assert_eq!(
-            dir.url_for("folder/child.md"),
-            Url::parse(index.search("Child")?.entries()[0].uri()).unwrap()
+            vec!["url", "title"],
+            json.as_object().unwrap().keys().map(String::as_str).collect::<Vec<_>>()
         );
+        assert_eq!("Projected", json["title"]);
+
+        let results = index.search_with("projected", &SearchOptions::default())?;
+        let entry = &results.entries()[0];
+        assert_eq!("Projected body\n", entry.markdown);
+        let json = serde_json::to_value(Projection { entry, fields: &Field::defaults() })?;
+        assert!(json.get("markdown").is_none(), "markdown isn't a default field");
+        assert!(json.get("links").is_none(), "links are only output when asked for");
+        assert!(json.get("score").is_some());
+
+        assert_eq!(Ok(Field::FileTitle), "file_title".parse());
+        assert!("body".parse::<Field>().is_err());
         Ok(())
     }
 
     #[test]
-    fn search_document_tests() -> Result<(), Box<dyn std::error::Error>> {
+    fn search_ndjson_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Streamed", "First body"),
+            NamedSource::new("Other", "Streamed in the body"),
+            NamedSource::new("Unrelated", "Nothing here"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let mut out = vec![];
+        let fields = [Field::Title, Field::Score];
+        let count = index.search_ndjson("streamed", &Default::default(), &fields, &mut out)?;
+
+        let lines: Vec<serde_json::Value> =
+            String::from_utf8(out)?.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(2, count);
+        assert_eq!(2, lines.len(), "each entry should be a line of its own");
+        assert_eq!("Streamed", lines[0]["title"], "title matches come first");
+        assert_eq!("Other", lines[1]["title"]);
+        assert!(lines[0]["score"].is_number());
+
+        let mut out = vec![];
+        let options = SearchOptions { limit: Some(1), ..Default::default() };
+        assert_eq!(1, index.search_ndjson("streamed", &options, &fields, &mut out)?);
+        assert_eq!(1, String::from_utf8(out)?.lines().count());
+        Ok(())
+    }
+
+    #[test]
+    fn search_duplicate_titles_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let (older, newer) = (DateTime::<Utc>::from(at(1)), DateTime::<Utc>::from(at(2)));
+        let documents = vec![
+            NamedSource::new("Index", "one").modified(older),
+            NamedSource::new("Index", "two").modified(newer),
+            NamedSource::new("Index", "six").modified(older),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents.clone())]);
+        index.refresh()?;
+
+        let order = |index: &Index| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search("index")?;
+            Ok(results.entries().iter().map(|entry| entry.markdown.trim().to_string()).collect())
+        };
+        let first = order(&index)?;
+        assert_eq!("two", first[0], "the most recently modified comes first");
+        let mut tied = first[1..].to_vec();
+        tied.sort_by_key(|markdown| {
+            let source = documents.iter().find(|source| source.content == *markdown).unwrap();
+            crate::markdown::Source::url(source).to_string()
+        });
+        assert_eq!(tied, first[1..], "then ordered by uri");
+
+        let mut reversed: Vec<NamedSource> = documents.into_iter().rev().collect();
+        reversed.rotate_left(1);
+        let mut index = Index::open_in_memory(vec![Box::new(reversed)]);
+        index.refresh()?;
+        assert_eq!(first, order(&index)?, "indexing order shouldn't matter");
+        Ok(())
+    }
+
+    #[test]
+    fn stats_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Alice", "---\ntype: Person\n---\nMet #people/friends for lunch"),
+            NamedSource::new("Bob", "[[type=person]] Lunch with #people"),
+            NamedSource::new("Paris", "---\ntype: Place\n---\nLunch"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let stats = index.stats(2)?;
+
+        assert_eq!(3, stats.documents);
+        assert_eq!(109, stats.bytes);
+        assert_eq!(14, stats.words);
+        assert_eq!(14.0 / 3.0, stats.average_words);
+        assert_eq!(3, stats.terms[0].documents);
+        assert_eq!("lunch", stats.terms[0].name);
+        let counts = |counts: &[Count]| -> Vec<(String, i64)> {
+            counts.iter().map(|count| (count.name.clone(), count.documents)).collect()
+        };
+        assert_eq!(
+            vec![("people".to_string(), 2), ("people/friends".to_string(), 1)],
+            counts(&stats.tags)
+        );
+        assert_eq!(vec![("Person".to_string(), 2), ("Place".to_string(), 1)], counts(&stats.types));
+        assert_eq!(None, stats.size, "in-memory indexes have no file");
+        Ok(())
+    }
+
+    #[test]
+    fn broken_links_tests() -> Result<(), Box<dyn std::error::Error>> {
         let dir = TestDir::new();
         let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
 
-        dir.write("plain.md", "Very SIMPLE document")?;
+        dir.write("Exists.md", "Exists")?;
+        dir.write(
+            "a.md",
+            "[[Exists]] [[exists#Part]] [[#Heading]] [[Missing]] [[type=person]] [[Missing|again]]",
+        )?;
+        dir.write("b.md", "[[folder/Gone.md]] [web](https://example.com/missing)")?;
+        dir.write("c.md", "[[Exists]] [[Nickname]] [[nickname#Part]] [[Exists.MD]]")?;
+        dir.write("Named.md", "---\naliases: [Nickname]\n---\nHas an alias")?;
+        dir.write("d.md", "[[image.png]] [[assets/Doc.PDF|the doc]] [[gone.png]]")?;
+        dir.write("assets/image.png", "")?;
+        dir.write("assets/doc.pdf", "")?;
         index.refresh()?;
 
-        for query in ["very", "VeRy", "simple", "SIMPLE", "document"] {
-            let results = index.search(query);
-            assert_eq!(1, results?.len(), "match word");
-        }
+        let broken = index.broken_links()?;
+        let pages: Vec<(&str, Vec<&str>)> = broken
+            .iter()
+            .map(|links| (links.title.as_str(), links.pages.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(
+            vec![("a", vec!["Missing"]), ("b", vec!["folder/Gone.md"]), ("d", vec!["gone.png"]),],
+            pages
+        );
 
-        for query in ["simple document", "very simple", "document simple very"] {
-            let results = index.search(query);
-            assert_eq!(1, results?.len(), "match multiple words");
-        }
+        dir.write("Missing.md", "Now it exists")?;
+        dir.write("folder/Gone.md", "And so does this")?;
+        dir.write("d.md", "[[image.png]]")?;
+        index.refresh()?;
+        assert!(index.broken_links()?.is_empty());
+        Ok(())
+    }
 
-        for query in ["missing", "simple missing document", "very simple missing"] {
-            let results = index.search(query);
-            assert_eq!(0, results?.len(), "don't match missing word");
-        }
+    #[test]
+    fn linking_to_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
 
-        for query in ["v", "si", "doc"] {
-            let results = index.search(query);
-            assert_eq!(1, results?.len(), "match word prefixes ({query})");
-        }
+        dir.write("Target.md", "---\ntitle: The Target\naliases: [Bullseye]\n---\nHit me")?;
+        dir.write("by-name.md", "See [[target]] and [[Target#Part]]")?;
+        dir.write("by-alias.md", "Aim for the [[Bullseye|middle]]")?;
+        dir.write("by-path.md", "[[folder/Target.md]]")?;
+        dir.write("by-extension.md", "[[Target.MD]]")?;
+        dir.write("unrelated.md", "[[Other]] [[#Target]]")?;
+        dir.write("folder/Other.md", "No links")?;
+        index.refresh()?;
+
+        let titles = |target: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let entries = index.linking_to(target)?;
+            Ok(entries.iter().map(|entry| entry.title().to_string()).collect())
+        };
+        let expected = vec!["by-alias", "by-extension", "by-name", "by-path"];
+        assert_eq!(expected, titles("Target")?);
+        assert_eq!(expected, titles("bullseye")?, "by alias");
+        assert_eq!(expected, titles("The Target")?, "by front matter title");
+        assert_eq!(expected, titles("Target.md")?, "by path within the vault");
+        let path = dir.path().join("Target.md");
+        assert_eq!(expected, titles(path.to_str().unwrap())?, "by absolute path");
+        assert_eq!(vec!["unrelated"], titles("folder/Other.md")?);
+        assert!(titles("by-name")?.is_empty());
+        assert!(index.linking_to("Missing").is_err());
         Ok(())
     }
 
     #[test]
-    fn search_inline_tags_tests() -> Result<(), Box<dyn std::error::Error>> {
+    fn edit_distance_tests() {
+        assert_eq!(0, edit_distance("markdown", "markdown"));
+        assert_eq!(2, edit_distance("markdonw", "markdown"));
+        assert_eq!(1, edit_distance("mark", "marks"));
+        assert_eq!(3, edit_distance("", "abc"));
+        assert_eq!(1, edit_distance("café", "cafe"));
+    }
+
+    #[test]
+    fn named_source_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let created = DateTime::<Utc>::from(at(1));
+        let documents = vec![
+            NamedSource::new("Old", "Old document").created(created).modified(created),
+            NamedSource::new("New", "New document").created(created).modified(at(3).into()),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let results = index.search("old")?;
+        assert_eq!("Old", results.entries()[0].title());
+        assert_eq!(created, results.entries()[0].modified);
+
+        let modified = SearchOptions { modified_since: Some(at(2).into()), ..Default::default() };
+        let results = index.search_with("document", &modified)?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["New"], titles);
+
+        index.connection.execute("UPDATE documents SET markdown = 'Unchanged'", ())?;
+        index.refresh()?;
+        let markdown: String =
+            index.connection.query_row("SELECT markdown FROM documents", [], |row| row.get(0))?;
+        assert_eq!("Unchanged", markdown, "unmodified documents should not be re-indexed");
+
+        let mut index = Index::open_in_memory(vec![Box::new(vec![NamedSource {
+            content: "Without metadata".to_string(),
+            ..Default::default()
+        }])]);
+        index.refresh()?;
+        assert_eq!(1, index.search("metadata")?.len(), "sources without metadata are indexed");
+        Ok(())
+    }
+
+    #[test]
+    fn search_title_only_tests() -> Result<(), Box<dyn std::error::Error>> {
         let dir = TestDir::new();
         let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
 
-        dir.write("doc.md", "Document with #inline tags #after")?;
+        dir.write("Recipes.md", "")?;
+        dir.write("Recipe index.md", "")?;
+        dir.write("Cooking.md", "A list of recipes")?;
         index.refresh()?;
 
-        for query in ["#inline", "#after"] {
-            let results = index.search(query);
-            assert_eq!(1, results?.len(), "match qualified tags");
-        }
+        let title_only = SearchOptions { title_only: true, ..Default::default() };
+        assert_eq!(3, index.search("recipe")?.len());
+        assert_eq!(2, index.search_with("recipe", &title_only)?.len());
 
-        for query in ["inline", "after"] {
-            let results = index.search(query);
-            assert_eq!(0, results?.len(), "don't match unqualified tag");
-        }
+        let best = SearchOptions { limit: Some(1), ..title_only };
+        assert_eq!(1, index.search_with("recipe", &best)?.len());
+        let all = SearchOptions { limit: Some(1), ..Default::default() };
+        assert_eq!(1, index.search_with("", &all)?.len(), "limits documents without a query");
+        Ok(())
+    }
+
+    #[test]
+    fn search_substring_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        dir.write("Notes.md", "Turn on debug logging")?;
+        dir.write("Blogging.md", "Writing posts")?;
+        dir.write("Other.md", "Nothing to see")?;
+        let substring = SearchOptions { substring: true, ..Default::default() };
+
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        index.refresh()?;
+        assert!(index.search_with("ogg", &substring).is_err(), "needs the substring index");
+        let tables = "SELECT count(*) FROM sqlite_master WHERE name = 'text_trigrams'";
+        let count: i64 = index.connection.query_row(tables, [], |row| row.get(0))?;
+        assert_eq!(0, count, "the substring index is only built when asked for");
+
+        let collection = Box::new(dir.path().to_path_buf());
+        let mut index = IndexBuilder::new().collection(collection).substring_index(true).build()?;
+        index.refresh()?;
+        assert!(index.search("ogg")?.is_empty(), "prefix search shouldn't match mid-word");
+        assert_eq!(2, index.search_with("ogg", &substring)?.len());
+        assert_eq!(1, index.search_with("ebu OGGING", &substring)?.len());
+        assert_eq!(1, index.search_with("blog", &substring)?.len());
+        assert!(index.search_with("og", &substring)?.is_empty(), "too short for trigrams");
+        Ok(())
+    }
+
+    #[test]
+    fn substring_index_kept_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let database = TestDir::new();
+        dir.write("Notes.md", "Turn on debug logging")?;
+        let substring = SearchOptions { substring: true, ..Default::default() };
+        let builder = || {
+            IndexBuilder::new()
+                .collection(Box::new(dir.path().to_path_buf()))
+                .path(database.path().join("index.sqlite"))
+        };
 
+        builder().substring_index(true).build()?.refresh()?;
+        let index = builder().build()?;
+        assert_eq!(1, index.size(), "opening without the setting shouldn't rebuild the index");
+        assert_eq!(1, index.search_with("ogg", &substring)?.len());
+
+        let index = builder().substring_index(false).build()?;
+        assert_eq!(0, index.size(), "turning it off rebuilds the index");
+        assert!(index.search_with("ogg", &substring).is_err());
+        assert!(!builder().build()?.config.substring_index, "and it stays off");
         Ok(())
     }
 
     #[test]
-    fn search_front_matter_tags_tests() -> Result<(), Box<dyn std::error::Error>> {
+    fn search_qualifier_tests() -> Result<(), Box<dyn std::error::Error>> {
         let dir = TestDir::new();
         let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write("Ada.md", "---\ntype: Person\n---\nMeeting notes #work")?;
+        dir.write("Standup.md", "---\ntype: Meeting\n---\nMeeting with Ada #work/daily")?;
+        dir.write("Other.md", "---\ntype: Person\n---\nNo meeting here")?;
+        index.refresh()?;
 
-        let content = indoc! {"
-            ---
-            tags: first, second
-            ---
-            Document with front matter tags
-        "};
+        let titles = |query: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search(query)?;
+            let mut titles: Vec<String> =
+                results.entries().iter().map(|entry| entry.title().to_string()).collect();
+            titles.sort();
+            Ok(titles)
+        };
+        assert_eq!(vec!["Ada", "Other"], titles("type:person meeting")?);
+        assert_eq!(vec!["Ada", "Other"], titles("TYPE:Person")?, "qualifiers alone filter");
+        assert_eq!(vec!["Ada", "Standup"], titles("tag:work meeting")?);
+        assert_eq!(vec!["Standup"], titles("title:stand meeting")?);
+        assert_eq!(vec!["Ada"], titles("title:ada type:person")?);
+        assert!(titles("type:meeting title:ada")?.is_empty());
+        assert_eq!(vec!["Standup"], titles("title:\"stand")?, "quotes shouldn't be query syntax");
+        assert_eq!(vec!["Ada", "Standup"], titles("ada")?, "unqualified words match text");
+        assert_eq!(None, qualifier("type:"));
+        assert_eq!(None, qualifier("near:5(a"));
+        Ok(())
+    }
 
-        dir.write("doc.md", content)?;
+    #[test]
+    fn search_exclude_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let index = Index::from_documents(vec![
+            NamedSource::new("Old plan", "The plan #archive/2022"),
+            NamedSource::new("Plan template", "---\ntype: Template\n---\nA plan #work"),
+            NamedSource::new("Plan", "---\ntype: Project\n---\nThe real plan #work"),
+            NamedSource::new("Loose plan", "A plan with no type"),
+        ])?;
+
+        let titles = |options: &SearchOptions| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search_with("plan", options)?;
+            let mut titles: Vec<String> =
+                results.entries().iter().map(|entry| entry.title().to_string()).collect();
+            titles.sort();
+            Ok(titles)
+        };
+        let archive = vec!["#Archive".to_string()];
+        let options = SearchOptions { exclude_tags: archive.clone(), ..Default::default() };
+        assert_eq!(vec!["Loose plan", "Plan", "Plan template"], titles(&options)?);
+
+        let options =
+            SearchOptions { exclude_types: vec!["template".into()], ..Default::default() };
+        assert_eq!(vec!["Loose plan", "Old plan", "Plan"], titles(&options)?);
+
+        let options = SearchOptions {
+            exclude_tags: archive,
+            exclude_types: vec!["Template".into()],
+            doc_type: Some("project".into()),
+            ..Default::default()
+        };
+        assert_eq!(vec!["Plan"], titles(&options)?, "exclusions combine with filters");
+        Ok(())
+    }
+
+    #[test]
+    fn search_match_any_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let index = Index::from_documents(vec![
+            NamedSource::new("Second", "Just apples"),
+            NamedSource::new("Both", "Apples and pears"),
+            NamedSource::new("Third", "Just pears"),
+            NamedSource::new("Neither", "Plums"),
+        ])?;
+
+        let titles = |options: &SearchOptions| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search_with("apple pear", options)?;
+            Ok(results.entries().iter().map(|entry| entry.title().to_string()).collect())
+        };
+        assert_eq!(vec!["Both"], titles(&SearchOptions::default())?, "every word by default");
+
+        let any = SearchOptions { match_any: true, ..Default::default() };
+        let matched = titles(&any)?;
+        assert_eq!(3, matched.len());
+        assert_eq!("Both", matched[0], "documents with more of the words rank higher");
+        assert!(!matched.contains(&"Neither".to_string()));
+        assert_eq!(
+            "{text} : (\"apple\"* OR \"pear\"*)",
+            index.plan("apple pear", &any)?.match_text()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn search_min_term_length_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let index = Index::from_documents(vec![
+            NamedSource::new("Apples", "A bag of apples"),
+            NamedSource::new("Bananas", "Bananas and apples"),
+            NamedSource::new("Cherries", "Bowl of cherries"),
+        ])?;
+
+        let min = |min_term_length| SearchOptions { min_term_length, ..Default::default() };
+        assert_eq!(2, index.search("a")?.len());
+        assert_eq!(2, index.search_with("a", &min(1))?.len());
+        assert!(index.search_with("a", &min(2))?.is_empty(), "nothing left to match");
+        assert!(index.search("z bag")?.is_empty());
+        assert_eq!(1, index.search_with("z bag", &min(2))?.len(), "short words are ignored");
+        assert_eq!(1, index.search_with("ba bowl", &min(3))?.len());
+        assert!(index.explain("a", &min(2))?.contains("nothing matches"));
+        Ok(())
+    }
+
+    #[test]
+    fn search_exact_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Pets", "A cat and a dog"),
+            NamedSource::new("Filing", "Each category has a label"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
         index.refresh()?;
 
-        for query in ["#first", "#second"] {
-            let results = index.search(query);
-            assert_eq!(1, results?.len(), "match qualified tags");
-        }
+        let exact = SearchOptions { exact: true, ..Default::default() };
+        assert_eq!(2, index.search("cat")?.len(), "prefixes match by default");
+        assert_eq!(1, index.search_with("cat", &exact)?.len());
+        assert_eq!("Pets", index.search_with("cat", &exact)?.entries()[0].title());
+        assert!(index.search_with("categ", &exact)?.is_empty());
+        assert_eq!(1, index.search_with("CATEGORY", &exact)?.len(), "ignoring case");
+        Ok(())
+    }
 
-        for query in ["first", "second"] {
-            let results = index.search(query);
-            assert_eq!(0, results?.len(), "don't match unqualified tag");
+    #[test]
+    fn search_fixed_string_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = vec![
+            NamedSource::new("Notes", "Set the type: field to either a OR b"),
+            NamedSource::new("Task", "---\ntype: task\n---\nChoose b or a"),
+            NamedSource::new("Quote", "She said \"hello\" twice"),
+        ];
+        let mut index = Index::open_in_memory(vec![Box::new(documents)]);
+        index.refresh()?;
+
+        let fixed = SearchOptions { fixed_string: true, ..Default::default() };
+        let titles = |query: &str, options: &SearchOptions| -> Vec<String> {
+            let results = index.search_with(query, options).unwrap();
+            results.entries().iter().map(|entry| entry.title().to_string()).collect()
+        };
+        assert_eq!(vec!["Task"], titles("type:task", &SearchOptions::default()));
+        assert!(titles("type:task", &fixed).is_empty(), "qualifiers are searched for literally");
+        assert_eq!(vec!["Notes"], titles("type:", &fixed));
+        assert_eq!(vec!["Notes"], titles("a OR b", &fixed));
+        assert_eq!(vec!["Quote"], titles("said \"hello", &fixed));
+        assert!(titles("the typ", &fixed).is_empty(), "words aren't expanded as prefixes");
+        assert_eq!(3, titles("  ", &fixed).len(), "an empty query matches everything");
+        Ok(())
+    }
+
+    #[test]
+    fn search_near_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("Close.md", "Rust makes writing async code pleasant")?;
+        dir.write(
+            "Apart.md",
+            "Rust is a language. Much later on in this note, we finally mention async",
+        )?;
+        index.refresh()?;
+
+        let titles = |query: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let results = index.search(query)?;
+            Ok(results.entries().iter().map(|entry| entry.title().to_string()).collect())
+        };
+        assert_eq!(2, titles("rust async")?.len());
+        assert_eq!(vec!["Close"], titles("near:2(rust async)")?, "within two tokens");
+        assert!(titles("near:1(rust async)")?.is_empty(), "beyond one token");
+        assert_eq!(2, titles("NEAR:12(rust async)")?.len());
+        assert_eq!(vec!["Close"], titles("near(rust async)")?, "defaults to ten tokens");
+        assert_eq!(vec!["Close"], titles("pleas near:2(rust async)")?, "combines with words");
+        assert!(titles("near:2(rus async)")?.is_empty(), "whole words only");
+
+        let substring = SearchOptions { substring: true, ..Default::default() };
+        assert!(index.search_with("near:2(rust async)", &substring).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn query_terms_tests() {
+        assert_eq!(vec!["a", "b"], query_terms(" a  b "));
+        assert_eq!(vec!["a", "near:5(b c)", "d"], query_terms("a near:5(b c) d"));
+        assert_eq!(vec!["NEAR(b c)"], query_terms("NEAR(b c)"));
+        assert_eq!(vec!["f(b", "c)"], query_terms("f(b c)"));
+        assert_eq!(None, near_query("near:x(a b)"));
+        assert_eq!(Some("NEAR(\"a\" \"b\", 3)".to_string()), near_query("near:3(a b)"));
+    }
+
+    #[test]
+    fn explain_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        let options = SearchOptions { language: Some("Rust".to_string()), ..Default::default() };
+        assert_eq!(
+            indoc! {r#"
+                Query: hello #Work
+                Title MATCH: {title} : "hello"*
+                Text MATCH: {text} : "hello"*
+                Filter: AND documents.id IN (SELECT document_id FROM document_tags WHERE tag = ?) -- 'work'
+                Filter: AND documents.id IN (SELECT document_id FROM document_languages WHERE language = ?) -- 'rust'
+            "#},
+            index.explain("hello #Work", &options)?
+        );
+
+        let options = SearchOptions { title_only: true, limit: Some(1), ..Default::default() };
+        assert_eq!(
+            indoc! {r#"
+                Query: it's
+                Title MATCH: {title} : "it's"*
+                Limit: 1
+            "#},
+            index.explain("it's", &options)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn search_min_score_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("strong.md", "Apple apple apple apple")?;
+        dir.write("weak.md", "An apple among many other words in a much longer document body")?;
+        for n in 0..5 {
+            dir.write(&format!("other-{n}.md"), "Unrelated")?;
         }
+        index.refresh()?;
+
+        let results = index.search("apple")?;
+        let scores: Vec<f64> = results.entries().iter().filter_map(Entry::score).collect();
+        assert_eq!(2, scores.len());
+        assert!(scores.iter().all(|score| (0.0..1.0).contains(score)), "{scores:?}");
+        let (strong, weak) = (scores[0].max(scores[1]), scores[0].min(scores[1]));
+        assert!(strong > weak);
+
+        let options =
+            SearchOptions { min_score: Some((strong + weak) / 2.0), ..Default::default() };
+        let results = index.search_with("apple", &options)?;
+        let titles: Vec<&str> = results.entries().iter().map(Entry::title).collect();
+        assert_eq!(vec!["strong"], titles);
+
+        let options = SearchOptions { min_score: Some(1.0), ..Default::default() };
+        assert_eq!(0, index.search_with("apple", &options)?.len());
+        let options = SearchOptions { min_score: Some(1.0), ..Default::default() };
+        assert_eq!(7, index.search_with("", &options)?.len(), "unscored results are kept");
+        Ok(())
+    }
+
+    #[test]
+    fn score_tests() {
+        assert_eq!(0.0, score(0.0));
+        assert_eq!(0.5, score(-1.0));
+        assert!(score(-10.0) > score(-1.0), "more negative ranks score higher");
+    }
+
+    #[test]
+    fn search_snippet_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
 
+        dir.write("apple.md", "A document about apples and pears")?;
+        dir.write("pears.md", "Nothing to see here")?;
+        index.refresh()?;
+
+        let results = index.search("pear")?;
+        assert_eq!(2, results.len());
+        let snippets: Vec<Option<&str>> = results.entries().iter().map(Entry::snippet).collect();
+        assert_eq!(
+            vec![
+                Some("\u{2}pears\u{3} Nothing to see here"),
+                Some("apple A document about apples and \u{2}pears\u{3}")
+            ],
+            snippets,
+            "title matches come first, text matches are marked"
+        );
+
+        let results =
+            index.search_with("", &SearchOptions { vault: None, ..Default::default() })?;
+        assert!(results.entries().iter().all(|entry| entry.snippet().is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn search_file_title_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+
+        dir.write("renamed-note.md", "---\ntitle: Original Note\n---\nSome note")?;
+        dir.write("plain-note.md", "Some note")?;
+        index.refresh()?;
+
+        let results = index.search_with("note", &SearchOptions::default())?;
+        let titles: Vec<(&str, Option<&str>)> =
+            results.entries().iter().map(|entry| (entry.title(), entry.file_title())).collect();
+        assert_eq!(
+            vec![("Original Note", Some("renamed-note")), ("plain-note", Some("plain-note"))],
+            sorted(titles)
+        );
         Ok(())
     }
 
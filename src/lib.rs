@@ -0,0 +1,19 @@
+//! The indexing and search behind the `markdown-db` cli, for use from other crates.
+//!
+//! Documents come from [`markdown::Collection`]s, such as a directory or an Obsidian vault, and
+//! are stored in an [`index::Index`] built with [`index::IndexBuilder`].
+
+pub mod error;
+pub mod export;
+pub mod highlight;
+pub mod http;
+pub mod index;
+pub mod markdown;
+pub mod obsidian;
+pub mod server;
+
+pub use markdown::source;
+
+/// Helpers for tests and benches.
+#[cfg(any(test, feature = "test-util"))]
+pub mod test;
@@ -1,52 +1,487 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use clap::{Parser, Subcommand};
-use index::Index;
-use log::{Level, Metadata, Record};
+use log::{warn, Level, Metadata, Record};
+use markdown_db::error::AppError;
+use markdown_db::index::{
+    Changes, Field, Group, GroupBy, Index, IndexBuilder, Projection, SearchOptions,
+};
+use markdown_db::markdown::collection::Directory;
+use markdown_db::markdown::DialectKind;
+use markdown_db::{export, highlight, http, index, server};
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode, DebounceEventResult};
 use rusqlite::Result;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{BufRead, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
 use directories::*;
 
-mod index;
-mod markdown;
-mod obsidian;
-
-#[cfg(test)]
-mod test;
-
-#[derive(Parser, Debug)]
-#[command(author, about, long_about = None)]
-
 /// markdown-db is a cli tool for searching and navigating markdown documents within Obsidian vaults (for now). It is
 /// designed to search documents very quickly and efficiently, building a local index when first called, then using
 /// this index for future operations.
+#[derive(Parser, Debug)]
+#[command(author, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
     /// Use an in-memory database instead of the default on-disk database.
     #[arg(long, global = true, env = "MARKDOWN_DB_IN_MEMORY", help_heading = "Database")]
     in_memory: bool,
+    /// Keep the index in this file instead of index.sqlite in the cache directory.
+    #[arg(
+        long,
+        global = true,
+        env = "MARKDOWN_DB_PATH",
+        value_name = "PATH",
+        conflicts_with = "in_memory",
+        help_heading = "Database"
+    )]
+    database: Option<PathBuf>,
+    /// Fail if the database is corrupt, instead of moving it aside and building a new index.
+    /// Only the parts read on opening are checked, so damage found later by a search or refresh
+    /// is an error either way, and deleting the database rebuilds it.
+    #[arg(long, global = true, help_heading = "Database")]
+    no_auto_repair: bool,
+    /// Move a corrupt --database file aside and build a new index there, as is done for the
+    /// default database. Without it, a corrupt --database file is an error.
+    #[arg(long, global = true, conflicts_with = "no_auto_repair", help_heading = "Database")]
+    auto_repair: bool,
+    /// Also make the values of this front matter field searchable as text, e.g. summary. Can be
+    /// given multiple times. The index keeps the fields it was built with until some are given
+    /// again, and --index-field "" indexes none. Changing the fields rebuilds the index.
+    #[arg(long = "index-field", value_name = "KEY", global = true, help_heading = "Database")]
+    index_fields: Vec<String>,
+    /// Also build the index --substring searches need, which takes around three times the space
+    /// of the word index. The index keeps the setting it was built with until it's given again,
+    /// and --substring-index=false turns it off. Changing it rebuilds the index.
+    #[arg(
+        long,
+        global = true,
+        env = "MARKDOWN_DB_SUBSTRING_INDEX",
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new(),
+        help_heading = "Database"
+    )]
+    substring_index: Option<bool>,
+    /// Search the existing index as-is, without checking the vaults for changes.
+    #[arg(long, global = true, help_heading = "Database")]
+    no_refresh: bool,
+    /// Markdown dialect to parse documents with: obsidian or commonmark.
+    #[arg(long, global = true, default_value = "obsidian", help_heading = "Sources")]
+    dialect: DialectKind,
+    /// Also index files with this extension in vaults and --dir folders, as well as md and
+    /// markdown. Can be given multiple times.
+    #[arg(long = "ext", global = true, help_heading = "Sources")]
+    extensions: Vec<String>,
+    /// Also index the markdown files in this folder, optionally with a dialect
+    /// (e.g. notes:commonmark). Can be given multiple times.
+    #[arg(long = "dir", global = true, value_name = "PATH[:DIALECT]", help_heading = "Sources")]
+    directories: Vec<Directory>,
+    /// Only index the Obsidian vaults that are currently open in Obsidian.
+    #[arg(long, global = true, help_heading = "Sources")]
+    open_vaults_only: bool,
+    /// Walk into symlinked folders in vaults and --dir folders. Symlinks that loop back on
+    /// themselves are skipped.
+    #[arg(long, global = true, help_heading = "Sources")]
+    follow_symlinks: bool,
+    /// Also index the markdown document at this URL. Can be given multiple times.
+    #[arg(long = "url", global = true, help_heading = "Sources")]
+    urls: Vec<url::Url>,
+    /// Write results to this file instead of stdout, creating its directory if needed. The file
+    /// is only replaced once the command succeeds.
+    #[arg(long, global = true, value_name = "PATH")]
+    output: Option<PathBuf>,
     #[arg(short, long, global = true)]
     /// Use verbose output
     verbose: bool,
 }
 
+impl Cli {
+    /// Whether the command writes JSON, in which case errors are written as JSON too.
+    fn json_output(&self) -> bool {
+        match &self.command {
+            Commands::Search(args) => args.format != SearchFormat::Plain,
+            Commands::Get(_) | Commands::Schema => true,
+            Commands::Info(InfoArgs { format, .. })
+            | Commands::Stats(StatsArgs { format, .. })
+            | Commands::Similar(SimilarArgs { format, .. })
+            | Commands::LinkingTo(LinkingToArgs { format, .. })
+            | Commands::BrokenLinks(BrokenLinksArgs { format, .. })
+            | Commands::Refresh(RefreshArgs { format, .. })
+            | Commands::Reindex(RefreshArgs { format, .. }) => *format == Format::Json,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Show information about the index and current configuration
-    Info,
+    Info(InfoArgs),
+    /// Show figures about the indexed documents: word counts and the most common terms, tags
+    /// and types
+    Stats(StatsArgs),
     /// Search for documents matching a query
-    Search(SearchArgs),
-    /// Reset the index
-    Reset,
+    Search(Box<SearchArgs>),
+    /// Find documents similar to the best match for a query
+    Similar(SimilarArgs),
+    /// List the documents with wiki links to a document
+    LinkingTo(LinkingToArgs),
+    /// Print a single document, with every field including its markdown, as JSON
+    Get(GetArgs),
+    /// Print the JSON Schema of search results written as JSON. Fields left out with --fields
+    /// are missing from the output
+    Schema,
+    /// List wiki links to pages that aren't in the index, exiting with status 1 if there are any
+    BrokenLinks(BrokenLinksArgs),
+    /// Write the markdown of indexed documents to files named by title in a directory
+    Export(ExportArgs),
+    /// Delete all indexed documents without re-scanning the vaults
+    Reset(ResetArgs),
+    /// Check the vaults for changes and update the index, reporting what changed
+    Refresh(RefreshArgs),
+    /// Delete all indexed documents, then rebuild the index from scratch
+    Reindex(RefreshArgs),
+    /// Remove a single document from the index, without re-scanning the vaults
+    Remove(RemoveArgs),
+    /// Keep the index up to date, re-indexing documents as they change
+    Watch(WatchArgs),
+    /// Answer line-delimited JSON search requests on stdin, keeping the index up to date
+    Serve(WatchArgs),
 }
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct SearchArgs {
-    /// Search query
+    /// Search query. When more than one is given, results are grouped by query. Words within
+    /// `near:N(first second)` match only when they're within N words of each other. Terms can be
+    /// qualified: `type:person` matches the document type, `tag:work` (or `#work`) a tag and
+    /// `title:word` a word of the title. Tags match whole, ignoring case, so `#work` finds #work
+    /// and #work/acme but not #workshop. A query of only qualifiers lists the most recently
+    /// modified documents first
+    #[arg()]
+    query: Vec<String>,
+    /// Print only the number of matching documents
+    #[arg(long)]
+    count: bool,
+    /// Exit with status 1 when no documents match the query
+    #[arg(long)]
+    fail_on_empty: bool,
+    /// Output format. ndjson writes each result as a line of JSON as soon as it's found
+    #[arg(long, value_enum, default_value_t = SearchFormat::Json)]
+    format: SearchFormat,
+    /// Color matched terms in plain output. Ignored when not writing to a terminal or NO_COLOR is set
+    #[arg(long)]
+    highlight: bool,
+    /// Print the full-text query and filters each search runs to stderr
+    #[arg(long)]
+    explain: bool,
+    /// Drop matches scoring below this. Scores run from 0 to 1, higher being a better match
+    #[arg(long, value_name = "SCORE")]
+    min_score: Option<f64>,
+    /// Return at most this many results for each query. Without the flag, MARKDOWN_DB_LIMIT is
+    /// used if it's set, otherwise every result is returned
+    #[arg(long, env = "MARKDOWN_DB_LIMIT")]
+    limit: Option<usize>,
+    /// Match the query against document titles only
+    #[arg(long)]
+    title_only: bool,
+    /// Include where each result's links point, resolving wiki links to documents where possible
+    #[arg(long)]
+    with_links: bool,
+    /// Fields to include in JSON output, from: id, title, file_title, url, path, type, markdown,
+    /// created, modified, score and links. Defaults to all but markdown
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<Field>,
+    /// Include the markdown of each result in JSON output
+    #[arg(long)]
+    with_markdown: bool,
+    /// When nothing matches, return documents with titles close to the query instead
+    #[arg(long)]
+    fuzzy: bool,
+    /// Ignore words of the query shorter than this. If every word is shorter, nothing matches
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_term_length: usize,
+    /// Match whole words only. By default each word also matches words starting with it, so cat
+    /// matches category
+    #[arg(long, conflicts_with = "substring")]
+    exact: bool,
+    /// Search for the query as one literal phrase, without reading qualifiers, tags or near
+    /// groups in it
+    #[arg(short = 'F', long)]
+    fixed_string: bool,
+    /// Match documents with any of the words of the query, instead of all of them
+    #[arg(long)]
+    match_any: bool,
+    /// Match words anywhere within words of the documents, not just at their start. Each word
+    /// needs at least three characters, and the index has to be built with --substring-index
+    #[arg(long)]
+    substring: bool,
+    /// Group results by type, tag, vault or folder. Documents appear under each of their tags
+    #[arg(long, value_name = "GROUP")]
+    group_by: Option<GroupBy>,
+    /// Return the document with exactly this title, or failing that alias, matching case, instead
+    /// of searching. Only documents passing the filters are looked at
+    #[arg(long, value_name = "TITLE", conflicts_with = "query")]
+    exact_title: Option<String>,
+    /// Only match documents from the vault with this name, as listed by info: its folder name,
+    /// followed by its id when another vault has a folder of the same name. Its id on its own,
+    /// from obsidian.json, can be given instead
+    #[arg(long, help_heading = "Filters")]
+    vault: Option<String>,
+    /// Only match documents containing a fenced code block in this language
+    #[arg(long, help_heading = "Filters")]
+    lang: Option<String>,
+    /// Only match documents of this type
+    #[arg(long = "type", value_name = "TYPE", help_heading = "Filters")]
+    doc_type: Option<String>,
+    /// Leave out documents with this tag, or a tag inside it. Can be given multiple times
+    #[arg(long, value_name = "TAG", help_heading = "Filters")]
+    exclude_tag: Vec<String>,
+    /// Leave out documents of this type. Can be given multiple times
+    #[arg(long, value_name = "TYPE", help_heading = "Filters")]
+    exclude_type: Vec<String>,
+    /// Only match documents in this folder of their vault, or folders inside it (e.g. Projects/Acme)
+    #[arg(long, help_heading = "Filters")]
+    folder: Option<String>,
+    /// Only match documents with this front matter value, e.g. status=active. Can be given
+    /// multiple times
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_meta, help_heading = "Filters")]
+    meta: Vec<(String, String)>,
+    /// Only match documents modified on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date, help_heading = "Filters")]
+    modified_since: Option<DateTime<Utc>>,
+    /// Only match documents created on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date, help_heading = "Filters")]
+    created_since: Option<DateTime<Utc>>,
+    /// Only match documents added or modified since the refresh before the most recent one, which
+    /// are the changes the most recent refresh found
+    #[arg(long, help_heading = "Filters")]
+    since_last_run: bool,
+    /// Leave out documents with `publish: false` or `draft: true` in their front matter
+    #[arg(long, help_heading = "Filters")]
+    published_only: bool,
+    /// With --published-only, also leave out documents that set neither publish nor draft
+    #[arg(long, requires = "published_only", help_heading = "Filters")]
+    unmarked_as_drafts: bool,
+}
+
+impl SearchArgs {
+    fn options(&self) -> SearchOptions {
+        SearchOptions {
+            vault: self.vault.clone(),
+            language: self.lang.clone(),
+            folder: self.folder.clone(),
+            metadata: self.meta.clone(),
+            doc_type: self.doc_type.clone(),
+            exclude_tags: self.exclude_tag.clone(),
+            exclude_types: self.exclude_type.clone(),
+            modified_since: self.modified_since,
+            created_since: self.created_since,
+            changed_since: None,
+            published_only: self.published_only,
+            unmarked_as_drafts: self.unmarked_as_drafts,
+            fuzzy: self.fuzzy,
+            min_term_length: self.min_term_length,
+            exact: self.exact,
+            fixed_string: self.fixed_string,
+            match_any: self.match_any,
+            substring: self.substring,
+            title_only: self.title_only,
+            min_score: self.min_score,
+            limit: self.limit,
+            with_links: self.with_links,
+            fields: Some(fields(&self.fields, self.with_markdown)),
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+struct SimilarArgs {
+    /// Query identifying the document to find others like
     #[arg()]
-    query: Option<String>,
+    query: String,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Return at most this many results
+    #[arg(long, default_value_t = 10)]
+    limit: usize,
+    /// Fields to include in JSON output, as for search
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<Field>,
+    /// Include the markdown of each result in JSON output
+    #[arg(long)]
+    with_markdown: bool,
+    /// Only return documents from the vault with this name, as listed by info, or its id
+    #[arg(long, help_heading = "Filters")]
+    vault: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct LinkingToArgs {
+    /// The document linked to: its title, an alias, or its path, absolute or within its vault
+    #[arg()]
+    target: String,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+    /// Fields to include in JSON output, as for search
+    #[arg(long, value_delimiter = ',')]
+    fields: Vec<Field>,
+    /// Include the markdown of each result in JSON output
+    #[arg(long)]
+    with_markdown: bool,
+}
+
+#[derive(Parser, Debug)]
+struct GetArgs {
+    /// The uri of the document, as given in search results
+    #[arg(required_unless_present = "id", conflicts_with = "id")]
+    uri: Option<String>,
+    /// The id of the document, as given in search results, instead of its uri
+    #[arg(long)]
+    id: Option<i64>,
+}
+
+/// The fields to output, or the defaults if none were chosen, along with the markdown if asked
+/// for. The markdown isn't read at all unless it's one of the fields.
+fn fields(fields: &[Field], with_markdown: bool) -> Vec<Field> {
+    let mut fields = if fields.is_empty() { Field::defaults() } else { fields.to_vec() };
+    if with_markdown && !fields.contains(&Field::Markdown) {
+        fields.push(Field::Markdown);
+    }
+    fields
+}
+
+/// Parses a front matter filter, splitting it at the first `=`.
+fn parse_meta(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("invalid filter `{value}`, expected KEY=VALUE")),
+    }
 }
 
+/// Parses an RFC 3339 timestamp, or a plain date taken as the start of that day in UTC.
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("invalid date `{value}`, expected YYYY-MM-DD or RFC 3339"))
+}
+
+#[derive(Parser, Debug, Clone)]
+struct InfoArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct StatsArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+    /// How many of the most common terms, tags and types to show
+    #[arg(long, default_value_t = 10)]
+    limit: usize,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Plain,
+    Json,
+}
+
+/// Search results can also be streamed as newline-delimited JSON.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchFormat {
+    Plain,
+    Json,
+    Ndjson,
+}
+
+#[derive(Serialize, Debug)]
+struct Info {
+    documents: i64,
+    path: Option<String>,
+    schema_version: i64,
+    vaults: Vec<VaultInfo>,
+}
+
+#[derive(Serialize, Debug)]
+struct VaultInfo {
+    name: String,
+    path: Option<String>,
+    documents: i64,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct ResetArgs {
+    /// Reset without asking for confirmation
+    #[arg(short, long, visible_alias = "yes")]
+    force: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct BrokenLinksArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct ExportArgs {
+    /// Directory to write the documents to, created if it doesn't exist
+    #[arg(long, value_name = "DIR")]
+    out: PathBuf,
+    /// Replace files in the directory with the same names as exported documents. Without this
+    /// nothing is exported if any of them exist
+    #[arg(short, long)]
+    force: bool,
+    /// Only export documents of this type
+    #[arg(long = "type", value_name = "TYPE", help_heading = "Filters")]
+    doc_type: Option<String>,
+    /// Only export documents with this tag. Can be given multiple times
+    #[arg(long, help_heading = "Filters")]
+    tag: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct RefreshArgs {
+    /// Report what would change without changing the index
+    #[arg(long)]
+    dry_run: bool,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct RemoveArgs {
+    /// The document's uri, or the path of its file
+    #[arg(value_name = "URI_OR_PATH")]
+    document: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct WatchArgs {
+    /// Milliseconds to wait for changes to a document to settle before re-indexing it
+    #[arg(long, default_value_t = 500)]
+    debounce: u64,
+}
+
+#[allow(dead_code)]
 struct SimpleLogger;
 
 impl log::Log for SimpleLogger {
@@ -56,69 +491,762 @@ impl log::Log for SimpleLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+            eprintln!("{} - {}", record.level(), record.args());
         }
     }
 
     fn flush(&self) {}
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    match run(&cli) {
+        Ok(code) => code,
+        Err(error) => {
+            let error = AppError::from(error);
+            if cli.json_output() {
+                eprintln!(
+                    "{}",
+                    serde_json::json!({ "error": error.to_string(), "kind": error.kind() })
+                );
+            } else {
+                eprintln!("Error: {error}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<ExitCode, Box<dyn std::error::Error>> {
     match &cli.command {
-        Commands::Reset => reset(&cli),
-        Commands::Search(args) => search(&cli, args),
-        Commands::Info => info(&cli),
+        Commands::Reset(args) => reset(cli, args).map(|_| ExitCode::SUCCESS),
+        Commands::Refresh(args) => {
+            with_output(cli, |out| refresh(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Reindex(args) => {
+            with_output(cli, |out| reindex(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Remove(args) => remove(cli, args),
+        Commands::Search(args) => with_output(cli, |out| search(cli, args, out)),
+        Commands::Similar(args) => {
+            with_output(cli, |out| similar(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::LinkingTo(args) => {
+            with_output(cli, |out| linking_to(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Get(args) => {
+            with_output(cli, |out| get(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Schema => with_output(cli, schema).map(|_| ExitCode::SUCCESS),
+        Commands::BrokenLinks(args) => with_output(cli, |out| broken_links(cli, args, out)),
+        Commands::Export(args) => {
+            with_output(cli, |out| export(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Info(args) => {
+            with_output(cli, |out| info(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Stats(args) => {
+            with_output(cli, |out| stats(cli, args, out)).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Watch(args) => watch(cli, args).map(|_| ExitCode::SUCCESS),
+        Commands::Serve(args) => serve(cli, args).map(|_| ExitCode::SUCCESS),
     }
 }
 
-fn index(cli: &Cli) -> Result<index::Index, Box<dyn std::error::Error>> {
-    let collections = obsidian::vaults().unwrap();
+/// The index, with the collections the options give. Documents at --url are only downloaded
+/// when `refreshing`, as they're only read when the index is refreshed.
+fn open_index(cli: &Cli, refreshing: bool) -> Result<index::Index, Box<dyn std::error::Error>> {
+    let mut builder = IndexBuilder::new()
+        .dialect(cli.dialect)
+        .follow_symlinks(cli.follow_symlinks)
+        .open_vaults_only(cli.open_vaults_only)
+        .auto_repair(auto_repair(cli))
+        .obsidian_vaults();
+    if let Some(substring_index) = cli.substring_index {
+        builder = builder.substring_index(substring_index);
+    }
+    for key in &cli.index_fields {
+        builder = builder.index_field(key);
+    }
+    for extension in &cli.extensions {
+        builder = builder.extension(extension);
+    }
+    for directory in &cli.directories {
+        builder = builder.directory(directory.clone());
+    }
+    if !cli.urls.is_empty() {
+        let urls = if refreshing {
+            http::HttpCollection::fetch(&cli.urls, cli.dialect)?
+        } else {
+            http::HttpCollection::unfetched(cli.dialect)
+        };
+        builder = builder.collection(Box::new(urls));
+    }
+    if !cli.in_memory {
+        builder = builder.path(database_path(cli));
+    }
+    builder.build()
+}
 
-    let mut index = if cli.in_memory {
-        Index::open_in_memory(collections)
-    } else {
-        let database_path = ProjectDirs::from("net", "warmdot", "markdown-db")
-            .unwrap()
-            .cache_dir()
-            .join("index.sqlite");
-        std::fs::create_dir_all(database_path.parent().unwrap())?;
-        Index::open_from_file(collections, database_path.as_path())
+/// Runs `command`, writing its results to stdout, or to the file given with --output. That's
+/// written to a temporary file next to it, which only replaces it once the command succeeds, so
+/// a command that fails leaves what was there before.
+fn with_output<T>(
+    cli: &Cli, command: impl FnOnce(&mut dyn Write) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let Some(path) = &cli.output else {
+        return command(&mut std::io::stdout());
     };
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+    let parent = parent.unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent)?;
+    let mut temp = tempfile::Builder::new();
+    // Temporary files are only readable by their owner, unlike the file they replace.
+    #[cfg(unix)]
+    temp.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    let mut out = BufWriter::new(temp.tempfile_in(parent)?);
+    let result = command(&mut out)?;
+    out.into_inner().map_err(|error| error.into_error())?.persist(path)?;
+    Ok(result)
+}
 
-    index.refresh()?;
-
+fn index(cli: &Cli) -> Result<index::Index, Box<dyn std::error::Error>> {
+    let mut index = open_index(cli, !cli.no_refresh)?;
+    if !cli.no_refresh {
+        index.refresh()?;
+    } else if index.size() == 0 {
+        eprintln!("Warning: the index is empty, run without --no-refresh to build it");
+    }
     Ok(index)
 }
 
-fn info(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+/// Whether a corrupt database is moved aside and rebuilt. The default database only ever holds an
+/// index, so is repaired unless --no-auto-repair is given, but a --database path could be any
+/// file, so is only repaired with --auto-repair.
+fn auto_repair(cli: &Cli) -> bool {
+    match cli.database {
+        Some(_) => cli.auto_repair,
+        None => !cli.no_auto_repair,
+    }
+}
+
+/// Where the index is kept: the --database path if given, otherwise in the cache directory.
+fn database_path(cli: &Cli) -> PathBuf {
+    match &cli.database {
+        Some(path) => path.clone(),
+        None => cache_dir(std::env::var_os("XDG_CACHE_HOME")).join("index.sqlite"),
+    }
+}
+
+/// The directory for markdown-db's cache. An absolute `XDG_CACHE_HOME` is used on every
+/// platform, as it already is on Linux, so the index can be kept somewhere predictable on macOS
+/// too.
+fn cache_dir(xdg_cache_home: Option<OsString>) -> PathBuf {
+    match xdg_cache_home.map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        Some(dir) => dir.join("markdown-db"),
+        None => ProjectDirs::from("net", "warmdot", "markdown-db").unwrap().cache_dir().into(),
+    }
+}
+
+fn info(cli: &Cli, args: &InfoArgs, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
     let index = index(cli)?;
-    println!("Index contains {} documents", index.size());
-    println!("Index path: {}", index.path().unwrap_or("a".to_string()));
+
+    if args.format == Format::Json {
+        let info = Info {
+            documents: index.size(),
+            path: index.path(),
+            schema_version: Index::schema_version(&index.connection),
+            vaults: index
+                .collections
+                .iter()
+                .zip(index.size_by_collection()?)
+                .map(|(collection, (name, documents))| VaultInfo {
+                    name,
+                    path: collection.root().map(|root| root.to_string_lossy().into_owned()),
+                    documents,
+                })
+                .collect(),
+        };
+        writeln!(out, "{}", serde_json::to_string_pretty(&info)?)?;
+        return Ok(());
+    }
+
+    writeln!(out, "Index contains {} documents", index.size())?;
+    writeln!(out, "Index path: {}", index.path().unwrap_or("(in memory)".to_string()))?;
+    for (name, documents) in index.size_by_collection()? {
+        writeln!(out, "  {name}: {documents} documents")?;
+    }
 
     Ok(())
 }
 
-fn search(cli: &Cli, args: &SearchArgs) -> Result<(), Box<dyn std::error::Error>> {
+fn stats(
+    cli: &Cli, args: &StatsArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
     let index = index(cli)?;
+    let stats = index.stats(args.limit)?;
+
+    if args.format == Format::Json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&stats)?)?;
+        return Ok(());
+    }
+
+    writeln!(out, "Documents: {} ({} bytes)", stats.documents, stats.bytes)?;
+    writeln!(out, "Words: {} ({:.1} per document)", stats.words, stats.average_words)?;
+    writeln!(out, "Distinct terms: {}", stats.distinct_terms)?;
+    if let Some(size) = stats.size {
+        writeln!(out, "Database size: {size} bytes")?;
+    }
+    for (heading, counts) in
+        [("terms", &stats.terms), ("tags", &stats.tags), ("types", &stats.types)]
+    {
+        if !counts.is_empty() {
+            writeln!(out, "Most common {heading}:")?;
+        }
+        for count in counts {
+            writeln!(out, "  {}: {} documents", count.name, count.documents)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn search(
+    cli: &Cli, args: &SearchArgs, out: &mut dyn Write,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+
+    let mut options = args.options();
+    if args.since_last_run {
+        // Before a second refresh, every document is new.
+        let since = index.previous_refresh()?.unwrap_or_default();
+        options.changed_since = Some(since);
+    }
+    if let Some(title) = &args.exact_title {
+        return find_by_title(&index, args, title, &options, out);
+    }
+    let queries = if !args.query.is_empty() {
+        args.query.clone()
+    } else if (SearchOptions { fields: None, ..options.clone() }) != SearchOptions::default() {
+        vec![String::new()]
+    } else {
+        writeln!(out, "Index contains {} documents", index.size())?;
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    if args.format == SearchFormat::Ndjson {
+        if args.group_by.is_some() {
+            return Err("--group-by can't be used with ndjson output".into());
+        }
+        let fields = options.fields.as_deref().unwrap_or_default();
+        let mut total = 0;
+        for query in &queries {
+            if args.explain {
+                eprint!("{}", index.explain(query, &options)?);
+            }
+            if args.count {
+                let count = index.search_with(query, &options)?.len();
+                writeln!(out, "{count}")?;
+                total += count;
+            } else {
+                total += index.search_ndjson(query, &options, fields, out)?;
+            }
+        }
+        if args.fail_on_empty && total == 0 {
+            return Ok(ExitCode::FAILURE);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.format == SearchFormat::Plain {
+        let terminal = cli.output.is_none() && std::io::stdout().is_terminal();
+        let color = highlight::enabled(args.highlight, terminal);
+        let mut total = 0;
+        for query in &queries {
+            if args.explain {
+                eprint!("{}", index.explain(query, &options)?);
+            }
+            let results = index.search_with(query, &options)?;
+            total += results.len();
+            if queries.len() > 1 {
+                writeln!(out, "{query}:")?;
+            }
+            let Some(by) = args.group_by else {
+                if args.count {
+                    writeln!(out, "{}", results.len())?;
+                } else {
+                    print_entries(out, results.entries().iter(), color)?;
+                }
+                continue;
+            };
+            for group in index.group(results.entries(), by)? {
+                if args.count {
+                    writeln!(out, "{}: {}", group_name(&group), group.entries.len())?;
+                } else {
+                    writeln!(out, "{}:", group_name(&group))?;
+                    print_entries(out, group.entries.into_iter(), color)?;
+                }
+            }
+        }
+        if args.fail_on_empty && total == 0 {
+            return Ok(ExitCode::FAILURE);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut output = serde_json::Map::new();
+    let mut total = 0;
+    for query in &queries {
+        if args.explain {
+            eprint!("{}", index.explain(query, &options)?);
+        }
+        let results = index.search_with(query, &options)?;
+        total += results.len();
+        let fields = options.fields.as_deref().unwrap_or_default();
+        let value = match args.group_by {
+            Some(by) => {
+                let groups = index.group(results.entries(), by)?;
+                let groups: serde_json::Map<_, _> = groups
+                    .into_iter()
+                    .map(|group| {
+                        let value = if args.count {
+                            serde_json::to_value(group.entries.len())
+                        } else {
+                            let entries = group.entries.iter();
+                            let projections: Vec<Projection> =
+                                entries.map(|entry| Projection { entry, fields }).collect();
+                            serde_json::to_value(projections)
+                        };
+                        (group_name(&group).to_string(), value.expect("Failed to serialize"))
+                    })
+                    .collect();
+                Ok(serde_json::Value::Object(groups))
+            }
+            None if args.count => serde_json::to_value(results.len()),
+            None => serde_json::to_value(project(results.entries(), fields)),
+        };
+        output.insert(query.clone(), value.expect("Failed to serialize results to JSON"));
+    }
+
+    let output = if queries.len() == 1 {
+        output.into_iter().next().unwrap().1
+    } else {
+        serde_json::Value::Object(output)
+    };
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&output).expect("Failed to serialize results to JSON")
+    )?;
+
+    if args.fail_on_empty && total == 0 {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_entries<'a>(
+    out: &mut dyn Write, entries: impl Iterator<Item = &'a index::Entry>, color: bool,
+) -> std::io::Result<()> {
+    for entry in entries {
+        writeln!(out, "{} ({})", entry.title(), entry.path().unwrap_or(entry.uri()))?;
+        if let Some(snippet) = entry.snippet() {
+            writeln!(out, "    {}", highlight::highlight(snippet, color).replace('\n', " "))?;
+        }
+        for link in entry.links().unwrap_or_default() {
+            writeln!(out, "    -> {link}")?;
+        }
+    }
+    Ok(())
+}
+
+/// The heading for a group of results, with entries that have no type, tag or folder under
+/// `(none)`.
+fn group_name<'a>(group: &'a Group) -> &'a str {
+    group.name.as_deref().unwrap_or("(none)")
+}
 
-    if let Some(query) = &args.query {
-        let results = index.search(query)?;
-        println!(
+fn find_by_title(
+    index: &Index, args: &SearchArgs, title: &str, options: &SearchOptions, out: &mut dyn Write,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let entries: Vec<index::Entry> = index.find_by_title(title, options)?.into_iter().collect();
+    if args.count {
+        writeln!(out, "{}", entries.len())?;
+    } else if args.format == SearchFormat::Plain {
+        for entry in &entries {
+            writeln!(out, "{} ({})", entry.title(), entry.path().unwrap_or(entry.uri()))?;
+        }
+    } else if args.format == SearchFormat::Ndjson {
+        let fields = fields(&args.fields, args.with_markdown);
+        for projection in project(&entries, &fields) {
+            writeln!(out, "{}", serde_json::to_string(&projection)?)?;
+        }
+    } else {
+        let fields = fields(&args.fields, args.with_markdown);
+        writeln!(
+            out,
             "{}",
-            serde_json::to_string_pretty(results.entries())
+            serde_json::to_string_pretty(&project(&entries, &fields))
                 .expect("Failed to serialize results to JSON")
-        );
+        )?;
+    }
+
+    if args.fail_on_empty && entries.is_empty() {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn similar(
+    cli: &Cli, args: &SimilarArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+    let options = SearchOptions {
+        vault: args.vault.clone(),
+        limit: Some(args.limit),
+        fields: Some(fields(&args.fields, args.with_markdown)),
+        ..Default::default()
+    };
+    let results = index.similar(&args.query, &options)?;
+
+    if args.format == Format::Plain {
+        for entry in results.entries() {
+            writeln!(out, "{} ({})", entry.title(), entry.path().unwrap_or(entry.uri()))?;
+        }
         return Ok(());
+    }
+
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&project(
+            results.entries(),
+            options.fields.as_deref().unwrap_or_default()
+        ))
+        .expect("Failed to serialize results to JSON")
+    )?;
+    Ok(())
+}
+
+fn linking_to(
+    cli: &Cli, args: &LinkingToArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+    let entries = index.linking_to(&args.target)?;
+
+    if args.format == Format::Plain {
+        for entry in &entries {
+            writeln!(out, "{} ({})", entry.title(), entry.path().unwrap_or(entry.uri()))?;
+        }
+        return Ok(());
+    }
+
+    let fields = fields(&args.fields, args.with_markdown);
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&project(&entries, &fields))
+            .expect("Failed to serialize results to JSON")
+    )?;
+    Ok(())
+}
+
+fn get(cli: &Cli, args: &GetArgs, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+    let entry = match (&args.uri, args.id) {
+        (_, Some(id)) => index.find_by_id(id)?.ok_or(format!("No document with id {id}"))?,
+        (Some(uri), None) => index.find_by_uri(uri)?.ok_or(format!("No document at {uri}"))?,
+        (None, None) => unreachable!("clap requires a uri or id"),
+    };
+    let projection = Projection { entry: &entry, fields: &Field::ALL };
+    writeln!(out, "{}", serde_json::to_string_pretty(&projection)?)?;
+    Ok(())
+}
+
+fn schema(out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = schemars::schema_for!(index::SearchResults);
+    writeln!(out, "{}", serde_json::to_string_pretty(&schema)?)?;
+    Ok(())
+}
+
+fn broken_links(
+    cli: &Cli, args: &BrokenLinksArgs, out: &mut dyn Write,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+    let broken = index.broken_links()?;
+
+    if args.format == Format::Json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&broken)?)?;
     } else {
-        println!("Index contains {} documents", index.size());
+        for links in &broken {
+            writeln!(out, "{} ({})", links.title, links.path.as_deref().unwrap_or(&links.url))?;
+            for page in &links.pages {
+                writeln!(out, "    {page}")?;
+            }
+        }
     }
 
+    Ok(if broken.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn export(
+    cli: &Cli, args: &ExportArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let index = index(cli)?;
+    let query: Vec<String> =
+        args.tag.iter().map(|tag| format!("#{}", tag.trim_start_matches('#'))).collect();
+    let options = SearchOptions { doc_type: args.doc_type.clone(), ..Default::default() };
+    let results = index.search_with(&query.join(" "), &options)?;
+
+    let paths = export::export(results.entries(), &args.out, args.force)?;
+    writeln!(out, "Exported {} documents to {}", paths.len(), args.out.display())?;
     Ok(())
 }
 
-fn reset(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
-    let mut index = index(cli)?;
+fn project<'a>(entries: &'a [index::Entry], fields: &'a [Field]) -> Vec<Projection<'a>> {
+    entries.iter().map(|entry| Projection { entry, fields }).collect()
+}
+
+fn confirm(prompt: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    print!("{prompt} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+fn reset(cli: &Cli, args: &ResetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if !cli.in_memory && !args.force {
+        if !std::io::stdout().is_terminal() {
+            return Err("Refusing to reset the index non-interactively, use --force".into());
+        }
+        let prompt =
+            format!("This will delete the index at {}. Continue?", database_path(cli).display());
+        if !confirm(&prompt)? {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+
+    let mut index = open_index(cli, false)?;
     index.reset()?;
     Ok(())
 }
+
+fn refresh(
+    cli: &Cli, args: &RefreshArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut index = open_index(cli, true)?;
+    let changes = index.refresh_changes(args.dry_run)?;
+    print_changes(out, args, &changes)?;
+    Ok(())
+}
+
+fn reindex(
+    cli: &Cli, args: &RefreshArgs, out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut index = open_index(cli, true)?;
+    let changes = index.reindex_changes(args.dry_run)?;
+    print_changes(out, args, &changes)?;
+    if !args.dry_run && args.format == Format::Plain {
+        writeln!(out, "Index contains {} documents", index.size())?;
+    }
+    Ok(())
+}
+
+fn print_changes(
+    out: &mut dyn Write, args: &RefreshArgs, changes: &Changes,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.format == Format::Json {
+        writeln!(out, "{}", serde_json::to_string_pretty(changes)?)?;
+        return Ok(());
+    }
+
+    let Changes { added, updated, removed, unchanged, .. } = changes;
+    if args.dry_run {
+        writeln!(
+            out,
+            "Would add {added}, update {updated}, remove {removed} and leave {unchanged} unchanged"
+        )?;
+    } else {
+        writeln!(
+            out,
+            "Added {added}, updated {updated}, removed {removed} and left {unchanged} unchanged"
+        )?;
+    }
+    if changes.skipped > 0 {
+        writeln!(out, "Skipped {} that couldn't be indexed", changes.skipped)?;
+    }
+    Ok(())
+}
+
+fn remove(cli: &Cli, args: &RemoveArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut index = open_index(cli, false)?;
+    let uri = match url::Url::parse(&args.document) {
+        Ok(uri) => uri,
+        Err(_) => {
+            let path = std::path::absolute(&args.document)?;
+            index.uri_for(&path).ok_or_else(|| format!("{} isn't in any vault", path.display()))?
+        }
+    };
+
+    if !index.remove(uri.as_str())? {
+        eprintln!("No document with uri {uri}");
+        return Ok(ExitCode::FAILURE);
+    }
+    println!("Removed {uri}");
+    Ok(ExitCode::SUCCESS)
+}
+
+fn watch(cli: &Cli, args: &WatchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut index = index(cli)?;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut debouncer = new_debouncer(Duration::from_millis(args.debounce), None, sender)?;
+    for root in index.roots() {
+        debouncer.watcher().watch(&root, RecursiveMode::Recursive)?;
+    }
+    println!("Watching {} documents for changes", index.size());
+
+    for events in receiver {
+        match events {
+            Ok(events) => {
+                // A file that can't be indexed, or a busy database, shouldn't stop the watching.
+                for event in events {
+                    match index.refresh_path(&event.path) {
+                        Ok(true) => println!("Indexed {}", event.path.display()),
+                        Ok(false) => {}
+                        Err(error) => warn!("Couldn't index {}: {error}", event.path.display()),
+                    }
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("Error watching for changes: {error}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Message {
+    Request(String),
+    Changes(DebounceEventResult),
+    Closed,
+}
+
+fn serve(cli: &Cli, args: &WatchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut index = index(cli)?;
+
+    // Requests and file changes share a channel, so the index is only used from this thread.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let changes = sender.clone();
+    let mut debouncer = new_debouncer(Duration::from_millis(args.debounce), None, move |events| {
+        let _ = changes.send(Message::Changes(events));
+    })?;
+    for root in index.roots() {
+        debouncer.watcher().watch(&root, RecursiveMode::Recursive)?;
+    }
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(Message::Request(line)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(Message::Closed);
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    for message in receiver {
+        match message {
+            Message::Request(line) if line.trim().is_empty() => {}
+            Message::Request(line) => {
+                writeln!(stdout, "{}", server::respond(&index, &line))?;
+                stdout.flush()?;
+            }
+            Message::Changes(Ok(events)) => {
+                // Requests keep being answered from the index as it was.
+                for event in events {
+                    if let Err(error) = index.refresh_path(&event.path) {
+                        warn!("Couldn't index {}: {error}", event.path.display());
+                    }
+                }
+            }
+            Message::Changes(Err(errors)) => {
+                for error in errors {
+                    eprintln!("Error watching for changes: {error}");
+                }
+            }
+            Message::Closed => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substring_index_tests() {
+        let cli = |args: &[&str]| Cli::try_parse_from(["markdown-db"].iter().chain(args)).unwrap();
+        assert_eq!(None, cli(&["info"]).substring_index, "the index keeps its setting");
+        assert_eq!(Some(true), cli(&["--substring-index", "info"]).substring_index);
+        assert_eq!(Some(false), cli(&["--substring-index=false", "info"]).substring_index);
+    }
+
+    #[test]
+    fn auto_repair_tests() {
+        let cli = |args: &[&str]| Cli::try_parse_from(["markdown-db"].iter().chain(args)).unwrap();
+        assert!(auto_repair(&cli(&["info"])), "the default database is repaired");
+        assert!(!auto_repair(&cli(&["--no-auto-repair", "info"])));
+        assert!(!auto_repair(&cli(&["--database", "notes.md", "info"])), "other files aren't");
+        assert!(auto_repair(&cli(&["--database", "index.sqlite", "--auto-repair", "info"])));
+    }
+
+    #[test]
+    fn database_path_tests() {
+        let cli = Cli::try_parse_from(["markdown-db", "--database", "/data/notes.sqlite", "info"])
+            .unwrap();
+        assert_eq!(PathBuf::from("/data/notes.sqlite"), database_path(&cli));
+
+        let cli = Cli::try_parse_from(["markdown-db", "info"]).unwrap();
+        assert_eq!("index.sqlite", database_path(&cli).file_name().unwrap());
+        assert!(
+            Cli::try_parse_from(["markdown-db", "--database", "a", "--in-memory", "info"]).is_err()
+        );
+
+        assert_eq!(PathBuf::from("/cache/markdown-db"), cache_dir(Some("/cache".into())));
+        assert_eq!(
+            cache_dir(None),
+            cache_dir(Some("relative".into())),
+            "relative paths are ignored"
+        );
+    }
+
+    #[test]
+    fn with_output_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("results").join("out.txt");
+        let cli = Cli::try_parse_from(["markdown-db", "--output", path.to_str().unwrap(), "info"])?;
+
+        with_output(&cli, |out| Ok(writeln!(out, "First")?))?;
+        assert_eq!("First\n", std::fs::read_to_string(&path)?);
+        let failed = with_output(&cli, |out| -> Result<(), Box<dyn std::error::Error>> {
+            writeln!(out, "Partial")?;
+            Err("failed".into())
+        });
+        assert!(failed.is_err());
+        assert_eq!("First\n", std::fs::read_to_string(&path)?, "a failure keeps the old output");
+        with_output(&cli, |_| Ok(()))?;
+        assert_eq!("", std::fs::read_to_string(&path)?, "success with no results replaces it");
+        assert_eq!(1, std::fs::read_dir(path.parent().unwrap())?.count(), "no files left over");
+        Ok(())
+    }
+}
@@ -1,18 +1,22 @@
 pub mod collection;
 pub mod source;
 
-use comrak::nodes::{Ast, NodeValue};
+use comrak::nodes::{Ast, AstNode, NodeValue};
 use comrak::{format_commonmark, Arena, ComrakOptions};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::str::FromStr;
 use url::Url;
 
 use chrono::{DateTime, Utc};
 
 pub use crate::obsidian::Obsidian;
 pub use collection::Collection;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
 pub use source::Source;
 
@@ -20,6 +24,62 @@ pub trait Dialect {
     fn parse<'a>(
         &self, arena: &'a Arena<comrak::arena_tree::Node<'a, RefCell<Ast>>>, source: &str,
     ) -> &'a comrak::arena_tree::Node<'a, RefCell<Ast>>;
+
+    /// Whether `%%comments%%` are hidden when rendering, so are left out of the document's text,
+    /// tags and links. They're kept in the parsed document, so its markdown still has them.
+    fn hides_comments(&self) -> bool {
+        false
+    }
+}
+
+/// Plain CommonMark, leaving `[[wiki links]]` and other Obsidian syntax as literal text. Front
+/// matter is still recognised, so its values aren't indexed as content.
+#[derive(Default, Debug)]
+pub struct CommonMark;
+
+impl Dialect for CommonMark {
+    fn parse<'a>(
+        &self, arena: &'a Arena<comrak::arena_tree::Node<'a, RefCell<Ast>>>, source: &str,
+    ) -> &'a comrak::arena_tree::Node<'a, RefCell<Ast>> {
+        let source = normalize_front_matter(source);
+        let options = ComrakOptions {
+            extension: comrak::ComrakExtensionOptions {
+                front_matter_delimiter: front_matter_delimiter(&source).map(str::to_owned),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        comrak::parse_document(arena, &source, &options)
+    }
+}
+
+/// Names the available dialects, so one can be chosen by configuration.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DialectKind {
+    #[default]
+    Obsidian,
+    CommonMark,
+}
+
+impl DialectKind {
+    pub fn dialect(self) -> Box<dyn Dialect> {
+        match self {
+            DialectKind::Obsidian => Box::new(Obsidian),
+            DialectKind::CommonMark => Box::new(CommonMark),
+        }
+    }
+}
+
+impl FromStr for DialectKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "obsidian" => Ok(DialectKind::Obsidian),
+            "commonmark" => Ok(DialectKind::CommonMark),
+            _ => Err(format!("unknown dialect `{name}`, expected obsidian or commonmark")),
+        }
+    }
 }
 
 pub trait DialectDocument<'a, T> {
@@ -34,12 +94,45 @@ impl<'a, T: Dialect + Default + 'static, S: Source + 'static> DialectDocument<'a
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FrontMatter {
+    #[serde(skip_serializing_if = "Option::is_none")]
     title: Option<String>,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     doc_type: Option<String>,
-    #[serde(default)]
+    /// Also merged from the other names notes use for tags, below.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[serde(deserialize_with = "FrontMatter::maybe_vec_of_strings")]
     tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing, deserialize_with = "FrontMatter::maybe_vec_of_strings")]
+    tag: Option<Vec<String>>,
+    #[serde(rename = "Tags")]
+    #[serde(default, skip_serializing, deserialize_with = "FrontMatter::maybe_vec_of_strings")]
+    capitalized_tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing, deserialize_with = "FrontMatter::maybe_vec_of_strings")]
+    keywords: Option<Vec<String>>,
+    /// Other names for the document, also merged from the older singular `alias` that Obsidian
+    /// still reads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(deserialize_with = "FrontMatter::maybe_aliases")]
+    aliases: Option<OneOrMany>,
+    #[serde(default, skip_serializing, deserialize_with = "FrontMatter::maybe_aliases")]
+    alias: Option<OneOrMany>,
+    #[serde(default, deserialize_with = "FrontMatter::maybe_bool")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publish: Option<bool>,
+    #[serde(default, deserialize_with = "FrontMatter::maybe_bool")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draft: Option<bool>,
+    /// Every other key, such as `status` or `cssclass`.
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// A front matter value written as either a single string or a list of them.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
 }
 
 impl FrontMatter {
@@ -88,16 +181,50 @@ impl FrontMatter {
             }
         }
 
-        Ok(match deserializer.deserialize_any(MaybeVecOfStrings(PhantomData)) {
-            Ok(result) => result,
+        Ok(deserializer.deserialize_any(MaybeVecOfStrings(PhantomData)).unwrap_or_default())
+    }
+
+    /// A yes/no flag, also written as a string such as `"false"` or `yes`. Anything else is
+    /// ignored rather than failing the whole front matter.
+    fn maybe_bool<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::Bool(value) => Some(value),
+            serde_yaml::Value::String(value) => match value.to_lowercase().as_str() {
+                "true" | "yes" => Some(true),
+                "false" | "no" => Some(false),
+                _ => None,
+            },
             _ => None,
         })
     }
 
+    /// Aliases written as a string or a list. Numbers and booleans are read as strings, and
+    /// anything else is dropped rather than failing the whole front matter.
+    fn maybe_aliases<'de, D>(deserializer: D) -> Result<Option<OneOrMany>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::Sequence(values) => {
+                Some(OneOrMany::Many(values.iter().filter_map(scalar).collect()))
+            }
+            value => scalar(&value).map(OneOrMany::One),
+        })
+    }
+
     pub fn title(&self) -> Option<&str> {
         self.title.as_deref()
     }
 
+    /// Whether the document is marked for publishing, with `publish`, or as not ready, with
+    /// `draft`. `publish` wins if both are set, and None means neither is.
+    pub fn published(&self) -> Option<bool> {
+        self.publish.or(self.draft.map(|draft| !draft))
+    }
+
     pub fn doc_type(&self) -> Option<&str> {
         self.doc_type.as_deref()
     }
@@ -105,18 +232,156 @@ impl FrontMatter {
     pub fn tags(&self) -> Option<&[String]> {
         self.tags.as_deref()
     }
+
+    /// Keys other than those read into fields, with their values as strings. A list gives a pair
+    /// for each of its items, and nested mappings or lists are left out.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![];
+        for (key, value) in &self.other {
+            let values = match value {
+                serde_yaml::Value::Sequence(values) => values.iter().filter_map(scalar).collect(),
+                value => scalar(value).into_iter().collect::<Vec<_>>(),
+            };
+            metadata.extend(values.into_iter().map(|value| (key.clone(), value)));
+        }
+        metadata
+    }
+
+    /// The document's aliases. Unlike tags, aliases can contain spaces, so a single string is
+    /// only split on commas.
+    pub fn aliases(&self) -> Vec<String> {
+        self.aliases.as_ref().map(OneOrMany::names).unwrap_or_default()
+    }
+}
+
+impl OneOrMany {
+    /// The names given, trimmed and without any empty ones.
+    fn names(&self) -> Vec<String> {
+        let names = match self {
+            OneOrMany::One(names) => names.split(',').map(str::to_string).collect(),
+            OneOrMany::Many(names) => names.clone(),
+        };
+        names.into_iter().map(|name| name.trim().to_string()).filter(|n| !n.is_empty()).collect()
+    }
+}
+
+/// A string, number or boolean front matter value as a string.
+fn scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(value) => Some(value.clone()),
+        serde_yaml::Value::Number(value) => Some(value.to_string()),
+        serde_yaml::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
 }
 
 impl From<&str> for FrontMatter {
     fn from(source: &str) -> Self {
-        serde_yaml::from_str(source).expect("Failed to parse front matter")
+        Self::from_yaml(source).expect("Failed to parse front matter").merge_tags().merge_aliases()
     }
 }
 
-impl From<&[u8]> for FrontMatter {
-    fn from(source: &[u8]) -> Self {
-        Self::from(String::from_utf8(source.into()).expect("Failed to parse front matter").as_str())
+impl FrontMatter {
+    /// Parses a front matter block including its delimiters, returning None unless it's a
+    /// mapping we can read fields from. The delimiters give the format: YAML between `---`, TOML
+    /// between `+++` or JSON between `;;;`.
+    pub fn parse(block: &[u8]) -> Option<Self> {
+        let block = String::from_utf8_lossy(block);
+        let delimiter = front_matter_delimiter(&block)?;
+        let data = block.trim_end().strip_prefix(delimiter)?.strip_suffix(delimiter)?;
+        let front_matter: Self = match delimiter {
+            "+++" => toml::from_str(data).ok(),
+            ";;;" => serde_json::from_str(data).ok(),
+            _ => Self::from_yaml(data).ok(),
+        }?;
+        Some(front_matter.merge_tags().merge_aliases())
     }
+
+    /// Moves tags given under the other names notes use, such as `Tags`, into `tags`. A note can
+    /// use more than one of them, so they're merged, leaving out repeats.
+    fn merge_tags(mut self) -> Self {
+        let others = [self.tag.take(), self.capitalized_tags.take(), self.keywords.take()];
+        for tag in others.into_iter().flatten().flatten() {
+            let tags = self.tags.get_or_insert_with(Vec::new);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        self
+    }
+
+    /// Moves aliases given under the singular `alias` into `aliases`, leaving out repeats, as a
+    /// note can have both.
+    fn merge_aliases(mut self) -> Self {
+        if let Some(alias) = self.alias.take() {
+            let mut aliases = self.aliases();
+            for name in alias.names() {
+                if !aliases.contains(&name) {
+                    aliases.push(name);
+                }
+            }
+            self.aliases = Some(OneOrMany::Many(aliases));
+        }
+        self
+    }
+
+    /// Parses YAML front matter. Keys written as numbers or booleans, such as `2023:`, are read
+    /// as strings, and keys that are lists or mappings are dropped rather than failing the whole
+    /// front matter.
+    fn from_yaml(data: &str) -> Result<Self, serde_yaml::Error> {
+        let mapping: serde_yaml::Mapping = serde_yaml::from_str(data)?;
+        let mapping = mapping
+            .into_iter()
+            .filter_map(|(key, value)| Some((serde_yaml::Value::String(scalar(&key)?), value)))
+            .collect();
+        serde_yaml::from_value(serde_yaml::Value::Mapping(mapping))
+    }
+}
+
+/// The delimiters front matter can be written between.
+const FRONT_MATTER_DELIMITERS: [&str; 3] = ["---", "+++", ";;;"];
+
+/// The delimiter of the front matter block `source` starts with, if its first line is one.
+pub fn front_matter_delimiter(source: &str) -> Option<&'static str> {
+    let first_line = source.lines().next()?.trim_end();
+    FRONT_MATTER_DELIMITERS.into_iter().find(|delimiter| *delimiter == first_line)
+}
+
+/// The part of `source` after its front matter block, or all of it if it has none.
+pub fn strip_front_matter(source: &str) -> &str {
+    let Some(delimiter) = front_matter_delimiter(source) else {
+        return source;
+    };
+    let mut end = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        end += line.len();
+        if i > 0 && line.trim_end() == delimiter {
+            return &source[end..];
+        }
+    }
+    source
+}
+
+/// Rewrites the delimiters of a leading front matter block so comrak recognises it even with
+/// trailing whitespace, CRLF line endings or nothing after the closing delimiter. Otherwise the
+/// block is parsed as content, and its values end up in the document's text.
+pub fn normalize_front_matter(source: &str) -> String {
+    let Some(delimiter) = front_matter_delimiter(source) else {
+        return source.to_string();
+    };
+
+    let mut lines = source.split_inclusive('\n').skip(1);
+    let mut output = format!("{delimiter}\n");
+    while let Some(line) = lines.next() {
+        if line.trim_end() == delimiter {
+            output.push_str(delimiter);
+            output.push('\n');
+            output.extend(lines);
+            return output;
+        }
+        output.push_str(line);
+    }
+    source.to_string()
 }
 
 impl Default for Box<dyn Dialect> {
@@ -125,12 +390,17 @@ impl Default for Box<dyn Dialect> {
     }
 }
 
+#[allow(dead_code)]
 trait DebuggableDialect: Dialect + std::fmt::Debug {}
 #[derive(Default)]
 pub struct Document<'a> {
     pub arena: Arena<comrak::arena_tree::Node<'a, RefCell<Ast>>>,
     pub root: OnceCell<Node<'a>>,
     pub front_matter: OnceCell<Option<FrontMatter>>,
+    pub text: OnceCell<String>,
+    pub heading: OnceCell<Option<String>>,
+    pub links: OnceCell<Vec<Link>>,
+    pub bytes: OnceCell<Vec<u8>>,
     pub source: Box<dyn Source>,
     pub dialect: Box<dyn Dialect>,
 }
@@ -142,10 +412,23 @@ impl Default for Box<dyn Source> {
 }
 
 impl<'a> Document<'a> {
+    /// A new document with the same source and dialect, but nothing parsed yet. A parsed
+    /// document borrows itself for its whole lifetime, which is shared by every document an
+    /// iterator returns, so each needs moving into its own document to be parsed and dropped
+    /// before the next.
+    pub fn unparsed<'b>(self) -> Document<'b> {
+        Document { source: self.source, dialect: self.dialect, ..Default::default() }
+    }
+
     pub fn uri(&self) -> Url {
         self.source.url()
     }
 
+    /// The canonical, absolute path of the document's file, if it has one.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.source.path().map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+    }
+
     pub fn modified(&'a self) -> Option<DateTime<Utc>> {
         self.source.modified()
     }
@@ -154,13 +437,64 @@ impl<'a> Document<'a> {
         self.source.created()
     }
 
-    pub fn title(&'a self) -> Option<&str> {
-        self.title_from_frontmatter().or(self.title_from_source())
+    /// The same as `display_title`.
+    pub fn title(&'a self) -> Option<&'a str> {
+        self.display_title()
+    }
+
+    /// The title to show for the document: the front matter title if there is one, then its first
+    /// level 1 heading, otherwise the file title.
+    pub fn display_title(&'a self) -> Option<&'a str> {
+        self.title_from_frontmatter().or_else(|| self.first_heading()).or(self.file_title())
+    }
+
+    /// The text of the document's first level 1 heading, if it has one that isn't empty.
+    pub fn first_heading(&'a self) -> Option<&'a str> {
+        self.heading.get_or_init(|| self.root().first_heading()).as_deref()
+    }
+
+    /// The title given by the document's source regardless of front matter, which for files is
+    /// the file name without its extension.
+    pub fn file_title(&self) -> Option<&str> {
+        self.title_from_source()
     }
 
     #[allow(dead_code)]
     pub fn content(&'a self) -> String {
-        self.source.read()
+        self.source_text().into_owned()
+    }
+
+    /// The source's bytes, read once and shared by parsing and the checksum. Fails if the source
+    /// can't be read, such as a file removed since it was listed.
+    pub fn bytes(&self) -> std::io::Result<&[u8]> {
+        self.bytes.get_or_try_init(|| self.source.bytes()).map(Vec::as_slice)
+    }
+
+    /// The source as text, with any invalid UTF-8 replaced. Empty if it can't be read.
+    fn source_text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.bytes().unwrap_or_default())
+    }
+
+    /// The length of the source in bytes.
+    pub fn len(&self) -> u64 {
+        self.bytes().map_or(0, |bytes| bytes.len() as u64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The SHA-256 of the source, in lowercase hex, to tell whether its content has changed.
+    pub fn checksum(&self) -> String {
+        source::hex_digest(self.bytes().unwrap_or_default())
+    }
+
+    /// The SHA-256 of the source after its front matter, which stays the same when only the
+    /// front matter is edited.
+    pub fn body_checksum(&self) -> String {
+        let source = self.source_text();
+        let source = source.strip_prefix('\u{feff}').unwrap_or(&source);
+        source::hex_digest(strip_front_matter(source).as_bytes())
     }
 
     pub fn markdown(&'a self) -> String {
@@ -169,35 +503,48 @@ impl<'a> Document<'a> {
         String::from_utf8(output).unwrap()
     }
 
-    fn type_from_link(&'a self) -> Option<String> {
-        let links = self.links();
-        let type_links = &mut links
-            .iter()
-            .filter(|link| link.meta().is_some() && link.meta().unwrap().0 == "type");
-        if let Some(link) = type_links.next() {
-            let result = link.meta().unwrap().1;
-            Some(result)
-        } else {
-            None
+    /// Like `markdown`, but with the front matter written back as a `---` block before the body.
+    /// Fields are written in a consistent order, which may differ from the original.
+    pub fn markdown_with_front_matter(&'a self) -> String {
+        let markdown = self.markdown();
+        match self.front_matter() {
+            Some(front_matter) => {
+                let yaml = serde_yaml::to_string(front_matter).unwrap();
+                format!("---\n{yaml}---\n\n{markdown}")
+            }
+            None => markdown,
         }
     }
 
+    /// The document's type. A `type` in front matter takes precedence over any `[[type=...]]`
+    /// links, and otherwise the first type link is used.
     pub fn doc_type(&'a self) -> Option<String> {
-        if let Some(frontmatter) = self.front_matter() {
-            frontmatter.doc_type().map(|s| s.to_owned())
-        } else {
-            self.type_from_link()
+        self.types().into_iter().next()
+    }
+
+    /// Every type signal in the document, front matter first and then type links in the order
+    /// they appear, without duplicates.
+    pub fn types(&'a self) -> Vec<String> {
+        let front_matter_type = self.front_matter().as_ref().and_then(|f| f.doc_type());
+        let link_types = self.links().iter().filter_map(|link| link.meta().remove("type"));
+
+        let mut types: Vec<String> = vec![];
+        for doc_type in front_matter_type.map(|t| t.to_owned()).into_iter().chain(link_types) {
+            if !types.contains(&doc_type) {
+                types.push(doc_type);
+            }
         }
+        types
     }
 
-    pub fn root(&'a self) -> &Node {
+    pub fn root(&'a self) -> &'a Node<'a> {
         if self.root.get().is_none() {
             self.init();
         };
         self.root.get().unwrap()
     }
 
-    pub fn front_matter(&'a self) -> &Option<FrontMatter> {
+    pub fn front_matter(&'a self) -> &'a Option<FrontMatter> {
         if self.front_matter.get().is_none() {
             self.init();
         };
@@ -205,36 +552,86 @@ impl<'a> Document<'a> {
     }
 
     pub fn init(&'a self) {
-        self.root.get_or_init(|| Node { node: self.parse() });
+        self.root.get_or_init(|| Node {
+            node: self.parse(),
+            hides_comments: self.dialect.hides_comments(),
+        });
         self.front_matter.get_or_init(|| {
-            self.root().node.children().find_map(|child| {
-                if let NodeValue::FrontMatter(data) = &child.data.borrow().value {
-                    child.detach();
-                    Some(FrontMatter::from(&data[4..(data.len() - 4)]))
-                } else {
-                    None
-                }
-            })
+            self.root()
+                .node
+                .children()
+                .find_map(|child| {
+                    if let NodeValue::FrontMatter(data) = &child.data.borrow().value {
+                        child.detach();
+                        Some(FrontMatter::parse(data))
+                    } else {
+                        None
+                    }
+                })
+                .flatten()
         });
     }
 
-    pub fn links(&'a self) -> Vec<Link> {
-        self.root().links()
+    pub fn links(&'a self) -> &'a [Link] {
+        self.links.get_or_init(|| self.root().links())
+    }
+
+    /// Tags from front matter followed by inline `#tags`, without the leading `#`. Tags differing
+    /// only in case are returned once, using the casing they first appear with.
+    pub fn tags(&'a self) -> Vec<String> {
+        let front_matter_tags =
+            self.front_matter().as_ref().and_then(|f| f.tags()).unwrap_or_default();
+        let mut seen = HashSet::new();
+        let mut tags: Vec<String> = vec![];
+        for tag in front_matter_tags
+            .iter()
+            .map(|tag| tag.trim_start_matches('#').to_string())
+            .chain(self.root().tags())
+        {
+            if !tag.is_empty() && seen.insert(tag.to_lowercase()) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+
+    /// Whether the document's front matter marks it as published or a draft.
+    pub fn published(&'a self) -> Option<bool> {
+        self.front_matter().as_ref().and_then(FrontMatter::published)
+    }
+
+    /// Other names for the document, from its front matter.
+    pub fn aliases(&'a self) -> Vec<String> {
+        self.front_matter().as_ref().map(FrontMatter::aliases).unwrap_or_default()
     }
 
-    pub fn text(&'a self) -> String {
-        self.root().text()
+    /// Front matter keys and values not covered by anything else, like `status: active`.
+    pub fn metadata(&'a self) -> Vec<(String, String)> {
+        self.front_matter().as_ref().map(FrontMatter::metadata).unwrap_or_default()
+    }
+
+    /// The document's plain text. Like `links`, this walks the whole tree, so it is only worked
+    /// out once.
+    pub fn text(&'a self) -> &'a str {
+        self.text.get_or_init(|| self.root().text())
+    }
+
+    pub fn code_languages(&'a self) -> Vec<String> {
+        self.root().code_languages()
     }
 
     fn parse(&'a self) -> &'a comrak::arena_tree::Node<'a, RefCell<Ast>> {
-        self.dialect.parse(&self.arena, &self.source.read())
+        let source = self.source_text();
+        // Some editors start files with a byte order mark, which would hide the front matter
+        // delimiter on the first line.
+        self.dialect.parse(&self.arena, source.strip_prefix('\u{feff}').unwrap_or(&source))
     }
 
     fn title_from_source(&self) -> Option<&str> {
         self.source.title()
     }
 
-    fn title_from_frontmatter(&'a self) -> Option<&str> {
+    fn title_from_frontmatter(&'a self) -> Option<&'a str> {
         if let Some(frontmatter) = self.front_matter() {
             frontmatter.title()
         } else {
@@ -245,15 +642,44 @@ impl<'a> Document<'a> {
 
 pub struct Node<'a> {
     pub node: &'a comrak::arena_tree::Node<'a, RefCell<Ast>>,
+    /// Whether `%%comments%%` are left out, see `Dialect::hides_comments`.
+    pub hides_comments: bool,
+}
+
+/// A node outside any `%%comment%%`, from `Node::visible`.
+struct Visible<'a> {
+    node: &'a AstNode<'a>,
+    /// For text nodes, the part of the text outside comments.
+    text: Option<Vec<u8>>,
+    /// Whether a comment is open where the node starts, as a heading can start inside one.
+    in_comment: bool,
 }
 
 impl<'a> Node<'a> {
+    /// The node's plain text. The `[!type]` marker starting an Obsidian callout, or a GitHub
+    /// alert, is left out, keeping the callout's title and body.
     pub fn text(&self) -> String {
+        self.text_from(false)
+    }
+
+    /// The node's plain text, where `in_comment` says whether it starts inside a comment.
+    fn text_from(&self, in_comment: bool) -> String {
+        static CALLOUT_PATTERN: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^\[![^\]\s]+\][+-]?[ \t]*").unwrap());
+
         let mut text: Vec<u8> = vec![];
-        let iter = self.node.descendants();
-        for node in iter {
+        // Where the text of the first paragraph of each blockquote starts, as that's where a
+        // callout's marker would be.
+        let mut quote_starts = vec![];
+        for visible in self.visible(in_comment) {
+            let node = visible.node;
+            let first_in_quote = node.previous_sibling().is_none()
+                && node.parent().is_some_and(|parent| {
+                    matches!(parent.data.borrow().value, NodeValue::BlockQuote)
+                });
             match &node.data.borrow().value {
-                NodeValue::Text(text_node) => text.extend(text_node),
+                NodeValue::Paragraph if first_in_quote => quote_starts.push(text.len()),
+                NodeValue::Text(_) => text.extend(visible.text.unwrap_or_default()),
                 NodeValue::Code(code) => text.extend(code.literal.clone()),
                 NodeValue::CodeBlock(block) => text.extend(block.literal.clone()),
                 NodeValue::HtmlInline(html) => text.extend(html),
@@ -261,25 +687,137 @@ impl<'a> Node<'a> {
                 _ => (),
             }
         }
-        String::from_utf8(text).expect("Unable to convert text to string")
+        // Removing from the end first leaves the earlier starts where they were.
+        for start in quote_starts.into_iter().rev() {
+            let marker = std::str::from_utf8(&text[start..])
+                .ok()
+                .and_then(|quote| CALLOUT_PATTERN.find(quote))
+                .map(|marker| marker.end());
+            if let Some(end) = marker {
+                text.drain(start..start + end);
+            }
+        }
+        String::from_utf8_lossy(&text).into_owned()
+    }
+
+    pub fn first_heading(&self) -> Option<String> {
+        self.visible(false).into_iter().find_map(|visible| match visible.node.data.borrow().value {
+            NodeValue::Heading(heading) if heading.level == 1 => {
+                let node = Node { node: visible.node, hides_comments: self.hides_comments };
+                let text = node.text_from(visible.in_comment);
+                let text = text.trim();
+                (!text.is_empty()).then(|| text.to_string())
+            }
+            _ => None,
+        })
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        static TAG_PATTERN: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"(?:^|\s)#(?P<tag>[\w/-]*[^\W\d_][\w/-]*)").unwrap());
+
+        let mut tags: Vec<String> = vec![];
+        for text in self.visible(false).into_iter().filter_map(|visible| visible.text) {
+            for captures in TAG_PATTERN.captures_iter(&String::from_utf8_lossy(&text)) {
+                tags.push(captures["tag"].trim_end_matches('/').to_string());
+            }
+        }
+        tags
+    }
+
+    /// The languages of fenced code blocks, taken from the first word of their info strings and
+    /// lowercased, each returned once.
+    pub fn code_languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = vec![];
+        for visible in self.visible(false) {
+            if let NodeValue::CodeBlock(block) = &visible.node.data.borrow().value {
+                let info = String::from_utf8_lossy(&block.info).to_lowercase();
+                if let Some(language) = info.split_whitespace().next() {
+                    if !languages.iter().any(|l| l == language) {
+                        languages.push(language.to_string());
+                    }
+                }
+            }
+        }
+        languages
     }
 
     pub fn links(&self) -> Vec<Link> {
         let mut links: Vec<Link> = vec![];
-        let iter = self.node.descendants();
-        for node in iter {
+        for visible in self.visible(false) {
+            let node = visible.node;
             if let NodeValue::Link(link) = &node.data.borrow().value {
-                let text = Node { node }.text();
-                let title = String::from_utf8(link.title.clone())
-                    .expect("Unable to convert title to string");
-                let url =
-                    String::from_utf8(link.url.clone()).expect("Unable to convert url to string");
+                let text = Node { node, hides_comments: self.hides_comments }.text();
+                let title = String::from_utf8_lossy(&link.title).into_owned();
+                let url = String::from_utf8_lossy(&link.url).into_owned();
 
                 links.push(Link::from(text, url, title));
             }
         }
         links
     }
+
+    /// The node and its descendants in document order, leaving out anything inside a comment
+    /// when comments are hidden. Comments can span lines and blocks, hiding any code or links
+    /// inside them, and like Obsidian, one that's never closed runs to the end of the document.
+    /// As comments are found in text nodes, `%%` in code isn't taken for one.
+    fn visible(&self, in_comment: bool) -> Vec<Visible<'a>> {
+        fn walk<'a>(
+            node: &'a AstNode<'a>, hides_comments: bool, in_comment: &mut bool,
+            visible: &mut Vec<Visible<'a>>,
+        ) {
+            let starts_in_comment = *in_comment;
+            let text = match &node.data.borrow().value {
+                NodeValue::Text(text) if hides_comments => Some(uncommented(text, in_comment)),
+                NodeValue::Text(text) => Some(text.clone()),
+                NodeValue::Code(_)
+                | NodeValue::CodeBlock(_)
+                | NodeValue::HtmlInline(_)
+                | NodeValue::HtmlBlock(_)
+                | NodeValue::Link(_)
+                | NodeValue::Image(_)
+                    if *in_comment =>
+                {
+                    return
+                }
+                _ => None,
+            };
+            visible.push(Visible { node, text, in_comment: starts_in_comment });
+            for child in node.children() {
+                walk(child, hides_comments, in_comment, visible);
+            }
+        }
+
+        let mut visible = vec![];
+        walk(
+            self.node,
+            self.hides_comments,
+            &mut (self.hides_comments && in_comment),
+            &mut visible,
+        );
+        visible
+    }
+}
+
+/// The parts of `text` outside `%%comments%%`, where `in_comment` says whether it starts inside
+/// one and is left saying whether it ends inside one.
+fn uncommented(text: &[u8], in_comment: &mut bool) -> Vec<u8> {
+    let mut visible = vec![];
+    let mut rest = text;
+    loop {
+        let delimiter = rest.windows(2).position(|pair| pair == b"%%");
+        let end = delimiter.unwrap_or(rest.len());
+        if !*in_comment {
+            visible.extend_from_slice(&rest[..end]);
+        }
+        match delimiter {
+            Some(start) => {
+                *in_comment = !*in_comment;
+                rest = &rest[start + 2..];
+            }
+            None => return visible,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -294,27 +832,49 @@ impl Link {
         Self { text, url, title }
     }
 
-    fn meta(&self) -> Option<(String, String)> {
-        if let Ok(url) = Url::parse(&self.url) {
-            let query = url.query_pairs().collect::<HashMap<_, _>>();
-            if let Some(key) = query.get("path") {
-                let parts: Vec<&str> = key.splitn(2, '=').collect();
-                if parts.len() > 1 {
-                    Some((parts[0].to_owned(), parts[1].to_owned()))
-                } else {
-                    None
-                }
-            } else {
-                None
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The page a wiki link points to, such as `Other Page` for `[[Other Page]]`, or None if
+    /// this isn't a link to an Obsidian page. Metadata links like `[[type=person]]` aren't to a
+    /// page either.
+    pub fn page(&self) -> Option<String> {
+        let url = Url::parse(&self.url).ok().filter(|url| url.scheme() == "obsidian")?;
+        let (_, page) = url.query_pairs().find(|(key, _)| key == "path")?;
+        (!page.contains('=')).then(|| page.into_owned())
+    }
+
+    /// Key/value metadata carried by the link. Obsidian encodes `[[type=person]]` as a link to
+    /// the page `type=person`, so a `path` holding `key=value` is read as metadata, as are any
+    /// other query parameters of an `obsidian://` link. Links that aren't URLs are read as
+    /// `key=value&...` pairs, and other URLs carry no metadata.
+    fn meta(&self) -> HashMap<String, String> {
+        let pairs: Vec<(String, String)> = match Url::parse(&self.url) {
+            Ok(url) if url.scheme() == "obsidian" => {
+                url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
             }
-        } else {
-            let parts: Vec<&str> = self.url.splitn(2, '=').collect();
-            if parts.len() > 1 {
-                Some((parts[0].to_owned(), parts[1].to_owned()))
-            } else {
-                None
+            Ok(_) => vec![],
+            Err(_) => url::form_urlencoded::parse(self.url.as_bytes())
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect(),
+        };
+
+        let mut meta = HashMap::new();
+        for (key, value) in pairs {
+            if key == "path" {
+                if let Some((key, value)) = value.split_once('=') {
+                    meta.insert(key.to_owned(), value.to_owned());
+                }
+            } else if !value.is_empty() {
+                meta.insert(key, value);
             }
         }
+        meta
     }
 }
 
@@ -342,6 +902,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn display_and_file_titles() -> Result<(), Box<dyn std::error::Error>> {
+        let test_dir = TestDir::new();
+
+        let path = test_dir.write("untitled-note.md", "## Heading")?;
+        let document = Obsidian::document(path);
+        assert_eq!(Some("untitled-note"), document.display_title());
+        assert_eq!(Some("untitled-note"), document.file_title());
+
+        let path = test_dir.write("Untitled.md", "# Heading")?;
+        let document = Obsidian::document(path);
+        assert_eq!(Some("Heading"), document.display_title());
+        assert_eq!(Some("Untitled"), document.file_title());
+
+        let path = test_dir.write("titled-note.md", "---\ntitle: Titled Note\n---\n")?;
+        let document = Obsidian::document(path);
+        assert_eq!(Some("Titled Note"), document.display_title());
+        assert_eq!(Some("titled-note"), document.file_title());
+
+        let document = Obsidian::document("---\ntitle: No file\n---\n");
+        assert_eq!(Some("No file"), document.display_title());
+        assert_eq!(None, document.file_title());
+        Ok(())
+    }
+
     #[test]
     fn title_from_frontmatter() -> Result<(), Box<dyn std::error::Error>> {
         let test_dir = TestDir::new();
@@ -370,6 +955,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn title_from_first_heading() -> Result<(), Box<dyn std::error::Error>> {
+        let test_dir = TestDir::new();
+
+        let path = test_dir.write("Untitled.md", "Intro\n\n## Part\n\n# The *real* `title`\n")?;
+        let document = Obsidian::document(path);
+        assert_eq!(Some("The real title"), document.first_heading());
+        assert_eq!(Some("The real title"), document.title(), "prefers heading to path");
+
+        let path =
+            test_dir.write("Titled.md", "---\ntitle: From front matter\n---\n# Heading\n")?;
+        let document = Obsidian::document(path);
+        assert_eq!(Some("From front matter"), document.title(), "prefers front matter to heading");
+
+        let document = Obsidian::document("#\n\nSetext\n======\n");
+        assert_eq!(Some("Setext"), document.first_heading(), "skips empty headings");
+        assert_eq!(None, Obsidian::document("## Only a subheading").first_heading());
+        Ok(())
+    }
+
     #[test]
     fn type_missing() {
         let document = Obsidian::document("");
@@ -409,6 +1014,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn type_precedence() -> Result<(), Box<dyn std::error::Error>> {
+        let source = indoc! {"
+            ---
+            type: person
+            ---
+            [[type=organization]] [[type=person]] [[type=place]]
+        "};
+
+        let document = Obsidian::document(source);
+        assert_eq!(Some("person".to_string()), document.doc_type(), "front matter wins");
+        assert_eq!(vec!["person", "organization", "place"], document.types());
+
+        let source = indoc! {"
+            ---
+            title: No type here
+            ---
+            [[type=organization]]
+        "};
+
+        let document = Obsidian::document(source);
+        assert_eq!(
+            Some("organization".to_string()),
+            document.doc_type(),
+            "links are used when front matter has no type"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn text_from_standard_content() -> Result<(), Box<dyn std::error::Error>> {
         assert_eq!(
@@ -447,10 +1082,160 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn strip_front_matter_tests() {
+        assert_eq!("Body\n", strip_front_matter("---\ntitle: A\n---\nBody\n"));
+        assert_eq!("Body", strip_front_matter("+++\r\ntitle = 'A'\r\n+++ \r\nBody"));
+        assert_eq!("", strip_front_matter("---\ntitle: A\n---"));
+        assert_eq!("---\nunclosed\n", strip_front_matter("---\nunclosed\n"));
+        assert_eq!("No front matter", strip_front_matter("No front matter"));
+    }
+
+    #[test]
+    fn text_from_callouts() -> Result<(), Box<dyn std::error::Error>> {
+        let text = |markdown: &str| Obsidian::document(markdown.to_string()).text().to_string();
+
+        assert_eq!("Hot stove", text("> [!warning]\n> Hot stove"));
+        assert_eq!("Careful", text("> [!warning] Careful"));
+        assert_eq!("Read this", text("> [!info]- Read this"));
+        let titled = text("> [!INFO] Custom title\n>\n> The body");
+        assert!(titled.starts_with("Custom title") && titled.ends_with("The body"));
+        assert_eq!("Quoted", text("> Quoted"), "plain quotes are unchanged");
+        assert_eq!(
+            "Not [!a] callout",
+            text("> Not [!a] callout"),
+            "markers only count at the start"
+        );
+        assert!(!text("> [!note]\n> > [!tip] Nested").contains("[!"));
+        Ok(())
+    }
+
+    #[test]
+    fn text_and_links_are_memoized() {
+        let document = Obsidian::document("Text with [[a link]]");
+
+        assert!(std::ptr::eq(document.text(), document.text()));
+        assert!(std::ptr::eq(document.links(), document.links()));
+    }
+
+    #[test]
+    fn source_is_read_once() {
+        struct Counting(std::rc::Rc<std::cell::Cell<usize>>);
+
+        impl Source for Counting {
+            fn read(&self) -> String {
+                self.0.set(self.0.get() + 1);
+                "---\ntitle: Counted\n---\nBody".to_string()
+            }
+
+            fn url(&self) -> Url {
+                Url::parse("memory:counting").unwrap()
+            }
+        }
+
+        let reads = std::rc::Rc::new(std::cell::Cell::new(0));
+        let document = Obsidian::document(Counting(reads.clone()));
+        assert_eq!(Some("Counted"), document.title());
+        assert_eq!("Body".checksum(), document.body_checksum());
+        assert_eq!(document.len(), document.content().len() as u64);
+        document.checksum();
+        assert_eq!(1, reads.get(), "parsing and checksums should share one read");
+    }
+
+    #[test]
+    fn code_languages_from_code_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            vec!["clojure", "rust"],
+            Obsidian::document(indoc! {"
+                ```clojure
+                (defn hello)
+                ```
+
+                ```Rust title=\"main.rs\"
+                fn main() {}
+                ```
+
+                ```
+                no language
+                ```
+
+                ```clojure
+                (defn goodbye)
+                ```
+            "})
+            .code_languages()
+        );
+        Ok(())
+    }
+
+    mod dialects {
+        use super::*;
+
+        #[test]
+        fn common_mark_leaves_wiki_links() {
+            let document = CommonMark::document(indoc! {"
+                ---
+                title: Plain
+                ---
+                [[NotAWikiLink]] and [a link](https://example.com)
+            "});
+
+            assert_eq!(Some("Plain"), document.title());
+            assert_eq!(1, document.links().len());
+            assert_eq!("https://example.com", document.links()[0].url);
+            assert_eq!("[[NotAWikiLink]] and a link", document.text());
+        }
+
+        #[test]
+        fn dialect_kind_from_str() {
+            assert_eq!(Ok(DialectKind::Obsidian), "obsidian".parse());
+            assert_eq!(Ok(DialectKind::CommonMark), "CommonMark".parse());
+            assert!("github".parse::<DialectKind>().is_err());
+        }
+    }
+
     mod links {
         use super::*;
         use similar_asserts::assert_eq;
 
+        fn meta(url: &str) -> HashMap<String, String> {
+            Link::from("".to_string(), url.to_string(), "".to_string()).meta()
+        }
+
+        fn pairs(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        }
+
+        #[test]
+        fn meta_single_parameter() {
+            assert_eq!(pairs(&[("type", "person")]), meta("obsidian://open?path=type%3Dperson"));
+            assert_eq!(pairs(&[("type", "person")]), meta("type=person"));
+            assert_eq!(pairs(&[]), meta("obsidian://open?path=Page"));
+            assert_eq!(pairs(&[]), meta("https://example.com/?type=rss"));
+        }
+
+        #[test]
+        fn meta_multiple_parameters() {
+            assert_eq!(
+                pairs(&[("type", "person"), ("status", "active")]),
+                meta("obsidian://open?path=Foo&type=person&status=active")
+            );
+            assert_eq!(
+                pairs(&[("type", "person"), ("status", "active")]),
+                meta("type=person&status=active")
+            );
+        }
+
+        #[test]
+        fn meta_percent_encoded_values() {
+            assert_eq!(
+                pairs(&[("type", "key person")]),
+                meta("obsidian://open?path=type%3Dkey%20person")
+            );
+            assert_eq!(pairs(&[("type", "a&b")]), meta("obsidian://open?path=Foo&type=a%26b"));
+            assert_eq!(pairs(&[("type", "key person")]), meta("type=key%20person"));
+        }
+
         #[test]
         fn markdown_link() {
             let document = Obsidian::document(indoc! {"
@@ -518,6 +1303,67 @@ mod tests {
                 document.links()[0]
             );
         }
+
+        #[test]
+        fn wiki_links_with_special_characters() {
+            let document = Obsidian::document(indoc! {"
+                [[Other Page]] [[Q&A]] [[C# Notes]] [[Why? Because]] [[Q&A|Questions]]
+            "});
+
+            let urls: Vec<_> = document.links().iter().map(|link| link.url.as_str()).collect();
+            assert_eq!(
+                vec![
+                    "obsidian://open?path=Other%20Page",
+                    "obsidian://open?path=Q%26A",
+                    "obsidian://open?path=C%23%20Notes",
+                    "obsidian://open?path=Why%3F%20Because",
+                    "obsidian://open?path=Q%26A",
+                ],
+                urls
+            );
+            for (link, page) in document.links().iter().zip(["Other Page", "Q&A", "C# Notes"]) {
+                assert_eq!(Some(page.to_string()), link.page());
+                let url = Url::parse(&link.url).unwrap();
+                assert_eq!(
+                    vec![("path".into(), page.into())],
+                    url.query_pairs().collect::<Vec<_>>()
+                );
+                assert!(link.meta().is_empty(), "page names aren't metadata");
+                assert!(document.markdown().contains(&link.url), "markdown uses the same url");
+            }
+        }
+
+        #[test]
+        fn wiki_links_with_surrounding_formatting() {
+            let document = Obsidian::document(indoc! {"
+                **[[Bold Link]]**, *[[Italic]]* and __[[Target|Bold Alias]]__!
+                ([[Parenthesised]]). «[[Café]]» and [[[Bracketed]]]
+            "});
+
+            let links: Vec<_> = document
+                .links()
+                .iter()
+                .map(|link| (link.text.as_str(), link.url.as_str()))
+                .collect();
+            assert_eq!(
+                vec![
+                    ("Bold Link", "obsidian://open?path=Bold%20Link"),
+                    ("Italic", "obsidian://open?path=Italic"),
+                    ("Bold Alias", "obsidian://open?path=Target"),
+                    ("Parenthesised", "obsidian://open?path=Parenthesised"),
+                    ("Café", "obsidian://open?path=Caf%C3%A9"),
+                    ("Bracketed", "obsidian://open?path=Bracketed"),
+                ],
+                links
+            );
+            assert_eq!(
+                indoc! {"
+                    **[Bold Link](obsidian://open?path=Bold%20Link)**, *[Italic](obsidian://open?path=Italic)* and **[Bold Alias](obsidian://open?path=Target)**\\!
+                    ([Parenthesised](obsidian://open?path=Parenthesised)). «[Café](obsidian://open?path=Caf%C3%A9)» and \\[[Bracketed](obsidian://open?path=Bracketed)\\]
+                "},
+                document.markdown()
+            );
+        }
     }
 
     #[test]
@@ -566,9 +1412,77 @@ mod tests {
         );
     }
 
+    mod tags {
+        use super::*;
+
+        #[test]
+        fn inline_tags() {
+            let document = Obsidian::document(indoc! {"
+                #first tag, then a #nested/tag/ and `#code` or https://example.com/#anchor
+
+                #2023 is not a tag but #y2023 is
+            "});
+
+            assert_eq!(vec!["first", "nested/tag", "y2023"], document.tags());
+        }
+
+        #[test]
+        fn front_matter_and_inline_tags() {
+            let document = Obsidian::document(indoc! {"
+                ---
+                tags: [first, '#second']
+                ---
+                Content with #second and #third
+            "});
+
+            assert_eq!(vec!["first", "second", "third"], document.tags());
+        }
+
+        #[test]
+        fn tags_differing_in_case() {
+            let document = Obsidian::document(indoc! {"
+                ---
+                tags: [Project]
+                ---
+                Content with #project and #PROJECT/Sub
+            "});
+
+            assert_eq!(vec!["Project", "PROJECT/Sub"], document.tags());
+        }
+    }
+
     mod front_matter {
         use super::*;
 
+        #[test]
+        fn delimiters() {
+            let sources = [
+                "---\ntitle: Delimited\ntags: [one, two]\n---\nBody\n",
+                "+++\ntitle = \"Delimited\"\ntags = [\"one\", \"two\"]\n+++\nBody\n",
+                ";;;\n{\"title\": \"Delimited\", \"tags\": [\"one\", \"two\"]}\n;;;\nBody\n",
+            ];
+            for dialect in [DialectKind::Obsidian, DialectKind::CommonMark] {
+                for source in sources {
+                    let document = Document {
+                        source: Box::new(source),
+                        dialect: dialect.dialect(),
+                        ..Default::default()
+                    };
+
+                    assert_eq!(Some("Delimited"), document.title(), "{source} with {dialect:?}");
+                    assert_eq!(vec!["one", "two"], document.tags(), "{source} with {dialect:?}");
+                    assert_eq!("Body\n", document.markdown(), "{source} with {dialect:?}");
+                }
+            }
+
+            let document = Obsidian::document("+++\ntitle = \"Mismatched\"\n---\nBody\n");
+            assert!(document.front_matter().is_none(), "delimiters should match");
+            let document = Obsidian::document("===\ntitle: Unknown\n===\n");
+            assert!(document.front_matter().is_none(), "only known delimiters start front matter");
+            assert_eq!(Some("+++"), front_matter_delimiter("+++ \r\ntitle = 'x'"));
+            assert_eq!(None, front_matter_delimiter("Text\n---\n"));
+        }
+
         #[test]
         fn tags_inline_array() {
             let front_matter = FrontMatter::from(indoc! {"
@@ -604,6 +1518,103 @@ mod tests {
             assert_eq!(["tag1", "tag2"], front_matter.tags().unwrap()[..]);
         }
 
+        #[test]
+        fn tags_field_aliases() {
+            for yaml in ["tag: one, two", "Tags: [one, two]", "keywords:\n  - one\n  - two"] {
+                let front_matter = FrontMatter::from(yaml);
+                assert_eq!(["one", "two"], front_matter.tags().unwrap_or_default(), "{yaml}");
+                assert!(front_matter.metadata().is_empty(), "{yaml} shouldn't be metadata");
+            }
+
+            let front_matter = FrontMatter::from("title: Kept\ntags: [one, two]\nTags: two, three");
+            assert_eq!(Some("Kept"), front_matter.title());
+            assert_eq!(["one", "two", "three"], front_matter.tags().unwrap_or_default());
+        }
+
+        #[test]
+        fn aliases() {
+            let aliases = |yaml: &str| FrontMatter::from(yaml).aliases();
+
+            assert_eq!(vec!["First Name", "Second"], aliases("aliases: [First Name, Second]"));
+            assert_eq!(
+                vec!["First Name", "Second"],
+                aliases("aliases:\n  - First Name\n  - Second")
+            );
+            assert_eq!(vec!["First Name", "Second"], aliases("aliases: First Name, Second"));
+            assert_eq!(vec!["Old Style"], aliases("alias: Old Style"));
+            assert_eq!(
+                vec!["New", "Shared", "Old"],
+                aliases("aliases: [New, Shared]\nalias: Shared, Old"),
+                "both keys are read together"
+            );
+            assert!(aliases("aliases:\nanything: else").is_empty());
+            assert!(aliases("title: No aliases").is_empty());
+
+            assert_eq!(vec!["2023", "Kept"], aliases("aliases: [2023, Kept, {nested: map}]"));
+            assert_eq!(vec!["2023"], aliases("aliases: 2023"));
+            let front_matter = FrontMatter::from(
+                "title: Kept
+tags: [one]
+aliases: [2023]",
+            );
+            assert_eq!(Some("Kept"), front_matter.title(), "odd aliases keep the rest");
+            assert_eq!(Some(&["one".to_string()][..]), front_matter.tags());
+        }
+
+        #[test]
+        fn published() {
+            let published = |yaml: &str| FrontMatter::from(yaml).published();
+
+            assert_eq!(Some(true), published("publish: true"));
+            assert_eq!(Some(false), published("publish: false"));
+            assert_eq!(Some(false), published("draft: true"));
+            assert_eq!(Some(true), published("draft: no"));
+            assert_eq!(Some(false), published("publish: 'false'\ndraft: false"), "publish wins");
+            assert_eq!(None, published("title: Neither"));
+            assert_eq!(None, published("publish: later"));
+            assert_eq!(Some("Kept"), FrontMatter::from("title: Kept\npublish: [1]").title());
+        }
+
+        #[test]
+        fn metadata() {
+            let front_matter = FrontMatter::from(indoc! {"
+                title: Not metadata
+                alias: Nor this
+                status: active
+                priority: 2
+                rating: 4.5
+                archived: false
+                cssclasses: [wide, 3]
+                nested:
+                    key: value
+                empty:
+            "});
+
+            assert_eq!(
+                vec![
+                    ("archived", "false"),
+                    ("cssclasses", "wide"),
+                    ("cssclasses", "3"),
+                    ("priority", "2"),
+                    ("rating", "4.5"),
+                    ("status", "active"),
+                ],
+                front_matter
+                    .metadata()
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(vec!["Nor this"], front_matter.aliases());
+
+            let front_matter = FrontMatter::from("title: Kept\n2023: review\n[a, b]: list\n");
+            assert_eq!(Some("Kept"), front_matter.title(), "odd keys keep the rest");
+            let metadata = front_matter.metadata();
+            let metadata: Vec<_> =
+                metadata.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+            assert_eq!(vec![("2023", "review")], metadata);
+        }
+
         #[test]
         fn empty_tags() {
             let front_matter = FrontMatter::from(indoc! {"
@@ -614,6 +1625,62 @@ mod tests {
             assert!(front_matter.tags().is_none());
         }
 
+        #[test]
+        fn front_matter_excluded_from_text() {
+            for source in [
+                "---\nsecret: hunter2\n---\nBody",
+                "---\nsecret: hunter2\n---",
+                "---  \nsecret: hunter2\n---  \nBody",
+                "---\r\nsecret: hunter2\r\n---\r\nBody",
+            ] {
+                let document = Obsidian::document(source);
+                assert!(!document.text().contains("hunter2"), "front matter in text of {source:?}");
+                assert!(document.front_matter().is_some(), "front matter not parsed in {source:?}");
+            }
+        }
+
+        #[test]
+        fn front_matter_after_byte_order_mark() {
+            for dialect in [DialectKind::Obsidian, DialectKind::CommonMark] {
+                let document = Document {
+                    source: Box::new("\u{feff}---\ntitle: Marked\n---\n# Heading\n"),
+                    dialect: dialect.dialect(),
+                    ..Default::default()
+                };
+
+                assert_eq!(Some("Marked"), document.title(), "title with {dialect:?}");
+                assert_eq!("# Heading\n", document.markdown(), "markdown with {dialect:?}");
+            }
+        }
+
+        #[test]
+        fn list_front_matter() {
+            let document = Obsidian::document(indoc! {"
+                ---
+                - a
+                - b
+                ---
+                # Title
+            "});
+
+            assert!(document.front_matter().is_none());
+            assert_eq!(Some("Title"), document.title(), "falls back to the heading");
+            assert_eq!("# Title\n", document.markdown(), "front matter is still removed");
+        }
+
+        #[test]
+        fn scalar_front_matter() {
+            let document = Obsidian::document(indoc! {"
+                ---
+                just a string
+                ---
+                # Title
+            "});
+
+            assert!(document.front_matter().is_none());
+            assert_eq!("# Title\n", document.markdown(), "front matter is still removed");
+        }
+
         #[test]
         fn no_tags() {
             let front_matter = FrontMatter::from(indoc! {"
@@ -691,6 +1758,53 @@ mod tests {
             );
         }
 
+        #[test]
+        fn keeps_front_matter() {
+            let document = Obsidian::document(indoc! {"
+                ---
+                title: A Title
+                type: Person
+                tags: [one, two]
+                other: kept
+                ---
+                # Title
+
+                Content
+            "});
+
+            let markdown = document.markdown_with_front_matter();
+            let round_tripped = Obsidian::document(markdown.clone());
+
+            assert_eq!(
+                indoc! {"
+                    ---
+                    title: A Title
+                    type: Person
+                    tags:
+                    - one
+                    - two
+                    other: kept
+                    ---
+
+                    # Title
+
+                    Content
+                "},
+                markdown
+            );
+            assert_eq!(Some("A Title"), round_tripped.title());
+            assert_eq!(Some("Person".to_string()), round_tripped.doc_type());
+            assert_eq!(vec!["one", "two"], round_tripped.tags());
+            assert_eq!(document.markdown(), round_tripped.markdown());
+        }
+
+        #[test]
+        fn keeps_missing_front_matter_missing() {
+            let document = Obsidian::document("# Title\n");
+
+            assert_eq!("# Title\n", document.markdown_with_front_matter());
+        }
+
         #[test]
         fn normalizes_wiki_links() {
             let document = Obsidian::document(indoc! {"
@@ -1,37 +1,236 @@
 use std::{
     ffi::OsStr,
+    io::ErrorKind::NotFound,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use super::{DialectDocument, Document, Obsidian};
-use walkdir::WalkDir;
+use super::{source::NamedSource, DialectDocument, DialectKind, Document, Obsidian};
+use walkdir::{DirEntry, WalkDir};
+
+/// A collection's documents, read one at a time. An error means some documents couldn't be
+/// listed, such as a folder that can't be read, though the rest can still be used.
+pub type DocumentsIter<'a> = Box<dyn Iterator<Item = std::io::Result<Document<'a>>> + 'a>;
 
 pub trait Collection {
-    fn documents(&self) -> Vec<Document>;
+    fn documents(&self) -> Vec<Document<'_>>;
+
+    /// The collection's documents one at a time, so each can be dropped once it's been used
+    /// rather than holding every parsed document in memory at once. Collections that read their
+    /// documents lazily should override this, as by default it's the same as `documents`. Fails
+    /// if the collection can't be listed at all, such as a folder that's gone.
+    fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+        Ok(Box::new(self.documents().into_iter().map(Ok)))
+    }
+
+    /// A name identifying the collection, recorded against each of its documents.
+    fn name(&self) -> String;
+
+    /// Another way to refer to the collection, which may be shorter or more stable than its
+    /// name, such as an Obsidian vault's id.
+    fn id(&self) -> Option<String> {
+        None
+    }
+
+    /// The directory documents in this collection are read from, if any.
+    fn root(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// The document at `path`, if it belongs to this collection. The file itself may no longer
+    /// exist, in which case the document can still be used to find its uri.
+    fn document(&self, _path: &Path) -> Option<Document<'_>> {
+        None
+    }
+
+    /// The folder this collection is, when it's only a path with nothing of its own configured,
+    /// so the index can walk it with the extensions and symlink setting it was built with.
+    fn directory(&self) -> Option<Directory> {
+        None
+    }
 }
 
-fn documents<'a>(path: PathBuf) -> Vec<Document<'a>> {
-    WalkDir::new(path)
-        .into_iter()
-        .filter(|entry| {
-            entry
-                .as_ref()
-                .map(|entry| entry.path().extension() == Some(OsStr::new("md")))
-                .unwrap_or(false)
-        })
-        .filter_map(|entry| entry.ok())
-        .map(|entry| Obsidian::document(entry.path().to_path_buf()))
+/// The file extensions indexed unless others are configured.
+pub const DEFAULT_EXTENSIONS: &[&str] = &["md", "markdown"];
+
+pub(crate) fn is_markdown(path: &Path) -> bool {
+    DEFAULT_EXTENSIONS.iter().any(|extension| has_extension(path, extension))
+}
+
+/// The default extensions, followed by `extra` ones, which can be written with or without a
+/// leading `.`.
+pub fn extensions(extra: &[String]) -> Vec<String> {
+    DEFAULT_EXTENSIONS
+        .iter()
+        .map(|extension| extension.to_string())
+        .chain(extra.iter().map(|extension| extension.trim_start_matches('.').to_string()))
         .collect()
 }
 
+pub(crate) fn has_any_extension(path: &Path, extensions: &[String]) -> bool {
+    extensions.iter().any(|extension| has_extension(path, extension))
+}
+
+pub(crate) fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().and_then(OsStr::to_str).is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// The canonical form of `path`, so a file reached through symlinks or overlapping collections
+/// always gets the same uri. Paths that no longer exist are returned as they are.
+pub(crate) fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Documents under `path` with one of `extensions`. Symlinked folders are only walked into if
+/// `follow_links` is set; links that loop back on themselves are skipped rather than walked
+/// forever.
+fn documents<'a>(
+    path: PathBuf, dialect: DialectKind, extensions: Vec<String>, follow_links: bool,
+) -> DocumentsIter<'a> {
+    Box::new(
+        WalkDir::new(path)
+            .follow_links(follow_links)
+            .into_iter()
+            .filter_map(walked)
+            .filter(move |entry| {
+                entry.as_ref().map_or(true, |entry| has_any_extension(entry.path(), &extensions))
+            })
+            .map(move |entry| entry.map(|entry| document_at(entry.path(), dialect))),
+    )
+}
+
+/// A walked entry, or the error that stopped part of a folder being listed. Links that loop
+/// back on themselves and files removed mid-walk aren't errors, so are skipped.
+pub(crate) fn walked(entry: walkdir::Result<DirEntry>) -> Option<std::io::Result<DirEntry>> {
+    match entry {
+        Ok(entry) => Some(Ok(entry)),
+        Err(error) if error.loop_ancestor().is_some() => None,
+        Err(error) if error.io_error().is_some_and(|error| error.kind() == NotFound) => None,
+        Err(error) => Some(Err(error.into())),
+    }
+}
+
+fn document_at<'a>(path: &Path, dialect: DialectKind) -> Document<'a> {
+    Document { source: Box::new(canonical(path)), dialect: dialect.dialect(), ..Default::default() }
+}
+
 impl Collection for Path {
-    fn documents(&self) -> Vec<Document> {
-        documents(self.canonicalize().unwrap())
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.documents_iter().into_iter().flatten().filter_map(Result::ok).collect()
+    }
+
+    /// Documents with the default extensions, not walking into symlinked folders. An index built
+    /// with other settings walks the path as a `Directory` instead.
+    fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+        Ok(documents(self.canonicalize()?, DialectKind::Obsidian, extensions(&[]), false))
+    }
+
+    fn name(&self) -> String {
+        self.to_string_lossy().into_owned()
+    }
+
+    fn root(&self) -> Option<PathBuf> {
+        self.canonicalize().ok()
+    }
+
+    fn document(&self, path: &Path) -> Option<Document<'_>> {
+        (is_markdown(path) && path.starts_with(self.root()?))
+            .then(|| document_at(path, DialectKind::Obsidian))
+    }
+
+    fn directory(&self) -> Option<Directory> {
+        Some(Directory {
+            path: self.to_path_buf(),
+            dialect: DialectKind::Obsidian,
+            extensions: extensions(&[]),
+            follow_links: false,
+        })
     }
 }
 
 impl Collection for PathBuf {
-    fn documents(&self) -> Vec<Document> {
-        documents(self.canonicalize().unwrap())
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.as_path().documents()
+    }
+
+    fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+        self.as_path().documents_iter()
+    }
+
+    fn name(&self) -> String {
+        self.as_path().name()
+    }
+
+    fn root(&self) -> Option<PathBuf> {
+        self.as_path().root()
+    }
+
+    fn document(&self, path: &Path) -> Option<Document<'_>> {
+        self.as_path().document(path)
+    }
+
+    fn directory(&self) -> Option<Directory> {
+        self.as_path().directory()
+    }
+}
+
+/// A folder of markdown files, parsed with its own dialect. Written as `path` or
+/// `path:dialect` on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directory {
+    pub path: PathBuf,
+    pub dialect: DialectKind,
+    /// File extensions of the documents to index, without the leading `.`.
+    pub extensions: Vec<String>,
+    /// Whether to walk into symlinked folders.
+    pub follow_links: bool,
+}
+
+impl Collection for Directory {
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.documents_iter().into_iter().flatten().filter_map(Result::ok).collect()
+    }
+
+    fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+        let path = self.path.canonicalize()?;
+        Ok(documents(path, self.dialect, self.extensions.clone(), self.follow_links))
+    }
+
+    fn name(&self) -> String {
+        self.path.name()
+    }
+
+    fn root(&self) -> Option<PathBuf> {
+        self.path.root()
+    }
+
+    fn document(&self, path: &Path) -> Option<Document<'_>> {
+        (has_any_extension(path, &self.extensions) && path.starts_with(self.root()?))
+            .then(|| document_at(path, self.dialect))
+    }
+}
+
+impl FromStr for Directory {
+    type Err = String;
+
+    /// Only a suffix naming a dialect is split off, so other paths containing `:` are kept whole.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (path, dialect) = value
+            .rsplit_once(':')
+            .and_then(|(path, dialect)| Some((path, dialect.parse().ok()?)))
+            .unwrap_or((value, DialectKind::default()));
+        let extensions = extensions(&[]);
+        Ok(Directory { path: PathBuf::from(path), dialect, extensions, follow_links: false })
+    }
+}
+
+/// Documents held in memory rather than read from files.
+impl Collection for Vec<NamedSource> {
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.iter().map(|source| Obsidian::document(source.clone())).collect()
+    }
+
+    fn name(&self) -> String {
+        "memory".to_string()
     }
 }
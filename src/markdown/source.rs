@@ -4,6 +4,7 @@ use chrono::{DateTime, Utc};
 use url::Url;
 
 use base64::Engine;
+use sha2::{Digest, Sha256};
 
 pub trait Source {
     fn read(&self) -> String;
@@ -24,6 +25,31 @@ pub trait Source {
     fn modified(&self) -> Option<DateTime<Utc>> {
         None
     }
+
+    /// The source's raw bytes. Files aren't always valid UTF-8, so these can differ from `read`,
+    /// and reading them fails if the file has gone.
+    fn bytes(&self) -> std::io::Result<Vec<u8>> {
+        Ok(self.read().into_bytes())
+    }
+
+    /// The length of the source in bytes, as `bytes` reads them. Zero if it can't be read.
+    fn len(&self) -> u64 {
+        self.bytes().map_or(0, |bytes| bytes.len() as u64)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The SHA-256 of the source's bytes, in lowercase hex, to tell whether its content has
+    /// changed. The same as `Document::checksum` for a document read from it.
+    fn checksum(&self) -> String {
+        hex_digest(&self.bytes().unwrap_or_default())
+    }
+}
+
+pub(crate) fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
 impl Source for String {
@@ -40,6 +66,52 @@ impl Source for String {
     }
 }
 
+/// In-memory markdown with its own title and timestamps, for documents that don't come from
+/// files but should still be searchable by title and date.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamedSource {
+    pub content: String,
+    pub title: Option<String>,
+    pub created: Option<DateTime<Utc>>,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+impl NamedSource {
+    pub fn new(title: &str, content: &str) -> Self {
+        Self { content: content.to_string(), title: Some(title.to_string()), ..Default::default() }
+    }
+
+    pub fn created(self, created: DateTime<Utc>) -> Self {
+        Self { created: Some(created), ..self }
+    }
+
+    pub fn modified(self, modified: DateTime<Utc>) -> Self {
+        Self { modified: Some(modified), ..self }
+    }
+}
+
+impl Source for NamedSource {
+    fn read(&self) -> String {
+        self.content.clone()
+    }
+
+    fn url(&self) -> Url {
+        self.content.url()
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn created(&self) -> Option<DateTime<Utc>> {
+        self.created
+    }
+
+    fn modified(&self) -> Option<DateTime<Utc>> {
+        self.modified
+    }
+}
+
 impl Source for &str {
     fn read(&self) -> String {
         self.to_string()
@@ -56,7 +128,7 @@ impl Source for &str {
 
 impl Source for PathBuf {
     fn read(&self) -> String {
-        std::fs::read_to_string(self).unwrap()
+        String::from_utf8_lossy(&std::fs::read(self).unwrap()).into_owned()
     }
 
     fn url(&self) -> Url {
@@ -78,4 +150,48 @@ impl Source for PathBuf {
     fn modified(&self) -> Option<DateTime<Utc>> {
         self.metadata().ok().and_then(|m| m.modified().ok()).map(|t| t.into())
     }
+
+    fn bytes(&self) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::Document;
+    use crate::test::TestDir;
+
+    #[test]
+    fn len_and_checksum() {
+        assert!(Source::is_empty(&""));
+        assert_eq!(11, Source::len(&"hello world"));
+        // "é" is two bytes in UTF-8.
+        assert_eq!(8, Source::len(&"résumé"));
+        assert_eq!(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            "hello world".checksum()
+        );
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "".checksum()
+        );
+
+        let named = NamedSource::new("Title", "hello world");
+        assert_eq!(11, named.len());
+        assert_eq!("hello world".checksum(), named.checksum());
+    }
+
+    #[test]
+    fn len_and_checksum_of_invalid_utf8() -> std::io::Result<()> {
+        let dir = TestDir::new();
+        let bytes = b"caf\xe9 latin-1";
+        let path = dir.write("latin.md", bytes)?;
+        assert_eq!(bytes.len() as u64, path.len(), "the raw bytes, not the replaced text");
+        assert_eq!(hex_digest(bytes), path.checksum());
+
+        let document = Document { source: Box::new(path.clone()), ..Default::default() };
+        assert_eq!((document.len(), document.checksum()), (path.len(), path.checksum()));
+        Ok(())
+    }
 }
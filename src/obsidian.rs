@@ -1,25 +1,42 @@
 use chrono::{DateTime, Utc};
 use comrak::{nodes::Ast, Arena, ComrakOptions};
 use directories::ProjectDirs;
+use log::warn;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
     collections::HashMap,
-    ffi::OsStr,
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
 
-use crate::markdown::{self, Collection, Dialect, Document};
+use crate::error::AppError;
+use crate::markdown::{
+    self,
+    collection::{self, canonical, has_any_extension, DocumentsIter},
+    front_matter_delimiter, normalize_front_matter, Collection, Dialect, DialectKind, Document,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Vault {
     pub id: String,
     pub path: String,
+    /// What the vault's documents are recorded against. Empty to use the folder name.
+    #[serde(skip)]
+    pub name: String,
+    #[serde(skip)]
+    pub dialect: DialectKind,
+    /// File extensions of the documents to index, without the leading `.`.
+    #[serde(skip)]
+    pub extensions: Vec<String>,
+    /// Whether to walk into symlinked folders.
+    #[serde(skip)]
+    pub follow_links: bool,
 }
 
 pub struct Source {
+    #[allow(dead_code)]
     pub vault: String,
     pub path: PathBuf,
 }
@@ -29,6 +46,10 @@ impl markdown::Source for Source {
         self.path.read()
     }
 
+    fn bytes(&self) -> std::io::Result<Vec<u8>> {
+        self.path.bytes()
+    }
+
     fn url(&self) -> url::Url {
         url::Url::parse(
             format!("obsidian://open?path={}", urlencoding::encode(&self.path.to_string_lossy()))
@@ -48,12 +69,23 @@ impl markdown::Source for Source {
     fn title(&self) -> Option<&str> {
         self.path.title()
     }
+
+    fn path(&self) -> Option<&PathBuf> {
+        Some(&self.path)
+    }
 }
 
+/// A vault as Obsidian records it in `obsidian.json`. Obsidian leaves out `open` for vaults
+/// that aren't open, and adds fields between versions, so only `path` is required and any
+/// other fields are ignored.
 #[derive(Debug, Serialize, Deserialize)]
 struct InnerVault {
     path: String,
+    /// When the vault was last opened, in milliseconds since the epoch.
+    #[serde(default)]
     ts: u64,
+    /// Whether the vault is open in an Obsidian window.
+    #[serde(default)]
     open: bool,
 }
 
@@ -66,54 +98,219 @@ impl Config {
     pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
         let config_path =
             ProjectDirs::from("", "", "obsidian").unwrap().config_dir().join("obsidian.json");
-        let config = std::fs::read_to_string(config_path)?;
-        let config: Config = serde_json::from_str(&config)?;
-        Ok(config)
+        match std::fs::read_to_string(&config_path) {
+            Ok(config) => Self::parse(&config),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Err(AppError::ConfigNotFound(config_path).into())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn parse(config: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(config)?)
+    }
+}
+
+/// The settings in a vault's `.obsidian/app.json` that hide files from Obsidian's own view of the
+/// vault: its attachment folder and the user's excluded files.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppConfig {
+    /// Where new attachments go. Obsidian also allows `/` for the vault root and paths starting
+    /// `./` for folders next to each note, neither of which are a single folder to exclude.
+    #[serde(default)]
+    attachment_folder_path: Option<String>,
+    /// Paths from the vault root that are excluded if a file's path starts with them, or
+    /// regular expressions written between slashes.
+    #[serde(default)]
+    user_ignore_filters: Vec<IgnoreFilter>,
+}
+
+/// One of a vault's excluded files filters, with any regular expression compiled once when the
+/// settings are read rather than for every file.
+#[derive(Debug)]
+enum IgnoreFilter {
+    Prefix(String),
+    Pattern(Regex),
+    /// A regular expression that doesn't compile, which like in Obsidian excludes nothing.
+    Invalid,
+}
+
+impl IgnoreFilter {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            IgnoreFilter::Prefix(prefix) => !prefix.is_empty() && path.starts_with(prefix.as_str()),
+            IgnoreFilter::Pattern(regex) => regex.is_match(path),
+            IgnoreFilter::Invalid => false,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IgnoreFilter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let filter = String::deserialize(deserializer)?;
+        Ok(match filter.strip_prefix('/').and_then(|filter| filter.strip_suffix('/')) {
+            Some(pattern) => {
+                Regex::new(pattern).map_or(IgnoreFilter::Invalid, IgnoreFilter::Pattern)
+            }
+            None => IgnoreFilter::Prefix(filter),
+        })
+    }
+}
+
+impl AppConfig {
+    /// The settings of the vault at `root`. Without an `app.json` nothing is excluded, as in
+    /// Obsidian, and one that can't be read is ignored.
+    fn read(root: &Path) -> Self {
+        let path = root.join(".obsidian").join("app.json");
+        let Ok(config) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        Self::parse(&config).unwrap_or_else(|error| {
+            warn!("Ignoring {}: {error}", path.display());
+            Self::default()
+        })
+    }
+
+    fn parse(config: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(config)
+    }
+
+    /// Whether Obsidian hides the file at `path`, relative to the vault root.
+    fn excludes(&self, path: &Path) -> bool {
+        self.hides(path.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/"))
+    }
+
+    /// Whether Obsidian hides the folder at `path`, relative to the vault root, so it needn't be
+    /// walked. A folder's path ends with `/`, as it would inside the paths of its files.
+    fn excludes_folder(&self, path: &Path) -> bool {
+        self.hides(path.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/") + "/")
+    }
+
+    fn hides(&self, path: String) -> bool {
+        let attachments = self
+            .attachment_folder_path
+            .as_deref()
+            .map(|folder| folder.trim_matches('/'))
+            .filter(|folder| !folder.is_empty() && !folder.starts_with('.'));
+        if attachments.is_some_and(|folder| path.starts_with(&format!("{folder}/"))) {
+            return true;
+        }
+        self.user_ignore_filters.iter().any(|filter| filter.matches(&path))
     }
 }
 
 impl Collection for Vault {
-    fn documents(&self) -> Vec<Document> {
-        let path = Path::new(&self.path);
-        WalkDir::new(path.canonicalize().unwrap())
-            .into_iter()
-            .filter(|entry| {
-                entry
-                    .as_ref()
-                    .map(|entry| entry.path().extension() == Some(OsStr::new("md")))
-                    .unwrap_or(false)
-            })
-            .filter_map(|entry| entry.ok())
-            .map(|entry| Document {
-                source: Box::new(Source {
-                    path: entry.path().to_path_buf(),
-                    vault: self.id.clone(),
-                }),
-                dialect: Box::new(Obsidian),
-                ..Default::default()
-            })
-            .collect()
+    fn documents(&self) -> Vec<Document<'_>> {
+        self.documents_iter().into_iter().flatten().filter_map(Result::ok).collect()
+    }
+
+    fn documents_iter(&self) -> std::io::Result<DocumentsIter<'_>> {
+        let root = Path::new(&self.path).canonicalize()?;
+        let app = AppConfig::read(&root);
+        let walk = WalkDir::new(&root).follow_links(self.follow_links).into_iter();
+        // Excluded folders are skipped whole rather than walked.
+        let excluded = move |entry: &walkdir::DirEntry| {
+            let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            if entry.file_type().is_dir() {
+                entry.depth() > 0 && app.excludes_folder(path)
+            } else {
+                app.excludes(path)
+            }
+        };
+        Ok(Box::new(
+            walk.filter_entry(move |entry| !excluded(entry))
+                .filter_map(collection::walked)
+                .filter(|entry| entry.as_ref().map_or(true, |entry| self.is_document(entry.path())))
+                .map(|entry| entry.map(|entry| self.document_at(entry.path()))),
+        ))
+    }
+
+    fn name(&self) -> String {
+        if self.name.is_empty() {
+            self.folder_name()
+        } else {
+            self.name.clone()
+        }
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    fn root(&self) -> Option<PathBuf> {
+        Path::new(&self.path).canonicalize().ok()
+    }
+
+    fn document(&self, path: &Path) -> Option<Document<'_>> {
+        let root = self.root()?;
+        let relative = path.strip_prefix(&root).ok()?;
+        (self.is_document(path) && !AppConfig::read(&root).excludes(relative))
+            .then(|| self.document_at(path))
     }
 }
 
-pub fn vaults() -> Result<Vec<Box<dyn Collection>>, Box<dyn std::error::Error>> {
+impl Vault {
+    /// The vault's folder name, which is how Obsidian itself names vaults.
+    fn folder_name(&self) -> String {
+        Path::new(&self.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.id.clone())
+    }
+
+    fn is_document(&self, path: &Path) -> bool {
+        has_any_extension(path, &self.extensions)
+    }
+
+    fn document_at(&self, path: &Path) -> Document<'_> {
+        Document {
+            source: Box::new(Source { path: canonical(path), vault: self.id.clone() }),
+            dialect: self.dialect.dialect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Every vault Obsidian knows about. Documents with any of the default extensions, or the
+/// `extensions` given, are indexed. Symlinked folders are walked into if `follow_links` is set,
+/// and with `open_only` vaults that aren't open in Obsidian are left out.
+pub fn vaults(
+    dialect: DialectKind, extensions: &[String], follow_links: bool, open_only: bool,
+) -> Result<Vec<Box<dyn Collection>>, Box<dyn std::error::Error>> {
+    let extensions = collection::extensions(extensions);
     let inner_vaults = Config::read()?.vaults;
-    let vaults = inner_vaults
+    let mut vaults: Vec<Vault> = inner_vaults
         .into_iter()
+        .filter(|(_, vault)| vault.open || !open_only)
         .map(|(id, vault)| {
-            let path = vault.path;
-            let vault = Vault { id, path };
-            Box::new(vault) as Box<dyn Collection>
+            let (path, name, extensions) = (vault.path, String::new(), extensions.clone());
+            Vault { id, path, name, dialect, extensions, follow_links }
         })
         .collect();
-    Ok(vaults)
+    name_vaults(&mut vaults);
+    Ok(vaults.into_iter().map(|vault| Box::new(vault) as Box<dyn Collection>).collect())
+}
+
+/// Names each vault by its folder, unless another vault has a folder of the same name, in which
+/// case its id is added so the two vaults' documents are never mixed up.
+fn name_vaults(vaults: &mut [Vault]) {
+    let folders: Vec<String> = vaults.iter().map(Vault::folder_name).collect();
+    for (vault, folder) in vaults.iter_mut().zip(&folders) {
+        let shared = folders.iter().filter(|other| *other == folder).count() > 1;
+        vault.name = if shared { format!("{folder} ({})", vault.id) } else { folder.clone() };
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Obsidian;
 
+/// Rewrite `[[wiki links]]` as markdown links before parsing, so emphasis or punctuation
+/// around a link never splits it across text nodes. Link targets can't contain brackets, which
+/// leaves any stray surrounding brackets as literal text.
 fn wiki_to_markdown_links(input: &str) -> String {
-    let link_pattern = Regex::new(r"\[\[(?P<url>[^\]|]+)(\|(?P<alias>[^\]]+))?\]\]").unwrap();
+    let link_pattern = Regex::new(r"\[\[(?P<url>[^\[\]|]+)(\|(?P<alias>[^\]]+))?\]\]").unwrap();
     link_pattern
         .replace_all(input, |caps: &regex::Captures| {
             let url = &caps["url"];
@@ -135,10 +332,10 @@ impl Dialect for Obsidian {
     fn parse<'a>(
         &self, arena: &'a Arena<comrak::arena_tree::Node<'a, RefCell<Ast>>>, source: &str,
     ) -> &'a comrak::arena_tree::Node<'a, RefCell<Ast>> {
-        let source = wiki_to_markdown_links(source);
+        let source = wiki_to_markdown_links(&normalize_front_matter(source));
         let options: ComrakOptions = ComrakOptions {
             extension: comrak::ComrakExtensionOptions {
-                front_matter_delimiter: Some("---".to_owned()),
+                front_matter_delimiter: front_matter_delimiter(&source).map(str::to_owned),
                 autolink: true,
                 ..Default::default()
             },
@@ -146,4 +343,170 @@ impl Dialect for Obsidian {
         };
         comrak::parse_document(arena, &source, &options)
     }
+
+    fn hides_comments(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::Index;
+    use crate::markdown::source::NamedSource;
+    use crate::markdown::DialectDocument;
+    use crate::test::TestDir;
+    use indoc::indoc;
+
+    #[test]
+    fn config_parse_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config::parse(
+            r#"{
+                "vaults": {
+                    "a1": {"path": "/notes/work", "ts": 1700000000000, "open": true},
+                    "b2": {"path": "/notes/home", "ts": 1600000000000},
+                    "c3": {"path": "/notes/old", "sync": false}
+                },
+                "updateDisabled": true
+            }"#,
+        )?;
+
+        assert_eq!(3, config.vaults.len(), "unknown and missing fields should be tolerated");
+        assert!(config.vaults["a1"].open);
+        assert!(!config.vaults["b2"].open, "vaults without `open` are closed");
+        assert_eq!(1600000000000, config.vaults["b2"].ts);
+        assert_eq!(0, config.vaults["c3"].ts);
+        assert!(Config::parse(r#"{"vaults": {"d4": {"open": true}}}"#).is_err(), "path is needed");
+        Ok(())
+    }
+
+    #[test]
+    fn name_vaults_tests() {
+        let vault = |id: &str, path: &str| Vault {
+            id: id.to_string(),
+            path: path.to_string(),
+            name: String::new(),
+            dialect: DialectKind::Obsidian,
+            extensions: vec![],
+            follow_links: false,
+        };
+        let mut vaults =
+            [vault("a1", "/work/notes"), vault("b2", "/home/notes"), vault("c3", "/home/journal")];
+        name_vaults(&mut vaults);
+
+        let names: Vec<String> = vaults.iter().map(Collection::name).collect();
+        assert_eq!(vec!["notes (a1)", "notes (b2)", "journal"], names);
+        assert_eq!("notes", vault("a1", "/work/notes").name(), "unnamed vaults use their folder");
+    }
+
+    #[test]
+    fn app_config_excludes_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        dir.write(
+            ".obsidian/app.json",
+            r#"{
+                "attachmentFolderPath": "Attachments",
+                "userIgnoreFilters": ["Archive/", "Drafts/old.md", "/^Temp.*\\.md$/"],
+                "showLineNumber": true
+            }"#,
+        )?;
+        for name in [
+            "Note.md",
+            "Attachments/Pasted.md",
+            "Archive/2020.md",
+            "Archived.md",
+            "Drafts/old.md",
+            "Drafts/new.md",
+            "Temporary.md",
+        ] {
+            dir.write(name, "Content")?;
+        }
+        let vault = Vault {
+            id: "test".to_string(),
+            path: dir.path().to_string_lossy().into_owned(),
+            name: String::new(),
+            dialect: DialectKind::Obsidian,
+            extensions: vec!["md".to_string()],
+            follow_links: false,
+        };
+
+        let mut titles: Vec<String> = vault
+            .documents()
+            .iter()
+            .map(|document| document.file_title().unwrap().to_string())
+            .collect();
+        titles.sort();
+        assert_eq!(vec!["Archived", "Note", "new"], titles);
+        let root = vault.root().unwrap();
+        assert!(vault.document(&root.join("Archive/2020.md")).is_none());
+        assert!(vault.document(&root.join("Note.md")).is_some());
+        let app = AppConfig::read(&root);
+        assert!(
+            app.excludes_folder(Path::new("Archive"))
+                && app.excludes_folder(Path::new("Attachments"))
+        );
+        assert!(
+            !app.excludes_folder(Path::new("Drafts")),
+            "folders with some files kept are walked"
+        );
+
+        let config = AppConfig::parse(r#"{"attachmentFolderPath": "./assets"}"#)?;
+        assert!(!config.excludes(Path::new("assets/a.md")), "folders next to notes aren't known");
+        assert!(!AppConfig::read(&root.join("missing")).excludes(Path::new("Archive/a.md")));
+        Ok(())
+    }
+
+    #[test]
+    fn comments_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let document = Obsidian::document(indoc! {"
+            Visible %%hidden inline%% text
+
+            %%
+            A hidden block
+            over lines
+            %%
+
+            Still visible %%unclosed runs to the end
+        "});
+        let text = document.text();
+        assert!(text.contains("Visible  text") && text.contains("Still visible"));
+        assert!(!text.contains("hidden") && !text.contains("unclosed"));
+
+        let document = Obsidian::document(indoc! {r#"
+            Print `printf("100%%")` then %%a [[Secret]] `code`%% the rest
+
+            ```sql
+            SELECT * FROM notes WHERE title LIKE '%%'
+            ```
+
+            After the code %%hidden%% shown
+        "#});
+        let text = document.text();
+        assert!(text.contains(r#"printf("100%%")"#), "inline code keeps its %%");
+        assert!(text.contains("LIKE '%%'"), "code blocks keep their %%");
+        assert!(text.contains("then  the rest") && text.contains("After the code  shown"));
+        assert!(!text.contains("Secret") && !text.contains("code`") && !text.contains("hidden"));
+        assert!(document.links().is_empty(), "links in comments aren't links");
+        assert!(
+            document.markdown().contains("%%a [Secret]"),
+            "the markdown keeps comments, only the text leaves them out"
+        );
+
+        let document =
+            Obsidian::document("%%\n# Draft heading\n%%\n\n# Real heading #shown %%#hidden%%\n");
+        assert_eq!(
+            Some("Real heading #shown"),
+            document.title(),
+            "headings in comments aren't titles"
+        );
+        assert_eq!(vec!["shown"], document.tags(), "tags in comments aren't tags");
+
+        let index = Index::from_documents(vec![NamedSource::new(
+            "Notes",
+            "Shown words %%secret scratch%% more shown",
+        )])?;
+        assert_eq!(1, index.search("shown")?.len());
+        assert!(index.search("secret")?.is_empty(), "commented text isn't searchable");
+        Ok(())
+    }
 }
@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::index::{Index, SearchOptions};
+
+/// A search request, read as a single line of JSON.
+#[derive(Deserialize, Debug)]
+struct Request {
+    query: String,
+    limit: Option<usize>,
+    #[serde(default)]
+    fuzzy: bool,
+}
+
+/// Answers a single line-delimited JSON request with the search results, or an error object
+/// when the request is malformed or the search fails. Any `id` in the request is echoed back so
+/// clients can match up responses.
+pub fn respond(index: &Index, line: &str) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => return json!({ "id": null, "error": format!("Invalid request: {error}") }),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(error) => return json!({ "id": id, "error": format!("Invalid request: {error}") }),
+    };
+
+    let options =
+        SearchOptions { fuzzy: request.fuzzy, limit: request.limit, ..Default::default() };
+    match index.search_with(&request.query, &options) {
+        Ok(results) => json!({ "id": id, "results": results.entries() }),
+        Err(error) => json!({ "id": id, "error": error.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestDir;
+
+    #[test]
+    fn respond_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TestDir::new();
+        let mut index = Index::open_in_memory(vec![Box::new(dir.path().to_path_buf())]);
+        dir.write("first.md", "Shared document")?;
+        dir.write("second.md", "Shared document")?;
+        index.refresh()?;
+
+        let response = respond(&index, r#"{"id": 1, "query": "shared"}"#);
+        assert_eq!(json!(1), response["id"]);
+        assert_eq!(2, response["results"].as_array().unwrap().len());
+
+        let response = respond(&index, r#"{"query": "shared", "limit": 1}"#);
+        assert_eq!(Value::Null, response["id"]);
+        assert_eq!(1, response["results"].as_array().unwrap().len());
+
+        let response = respond(&index, r#"{"query": "missing"}"#);
+        assert_eq!(json!([]), response["results"]);
+
+        let response = respond(&index, r#"{"query": "secnd", "fuzzy": true}"#);
+        assert_eq!(json!("second"), response["results"][0]["title"]);
+        Ok(())
+    }
+
+    #[test]
+    fn respond_error_tests() {
+        let index = Index::open_in_memory(vec![]);
+
+        let response = respond(&index, "not json");
+        assert!(response["error"].as_str().unwrap().starts_with("Invalid request"));
+
+        let response = respond(&index, r#"{"id": "a", "limit": 1}"#);
+        assert_eq!(json!("a"), response["id"]);
+        assert!(response["error"].as_str().unwrap().contains("missing field `query`"));
+    }
+}
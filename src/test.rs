@@ -1,4 +1,7 @@
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
 
 use tempfile::TempDir;
 use url::Url;
@@ -7,6 +10,12 @@ pub struct TestDir {
     temp_dir: TempDir,
 }
 
+impl Default for TestDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TestDir {
     pub fn new() -> Self {
         let temp_dir = TempDir::new().unwrap();
@@ -21,13 +30,22 @@ impl TestDir {
         Url::from_file_path(self.path().join(name).canonicalize().unwrap()).unwrap()
     }
 
-    pub fn write(&self, name: &str, contents: &str) -> std::io::Result<PathBuf> {
-        std::thread::sleep(std::time::Duration::from_millis(5));
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> std::io::Result<PathBuf> {
         let path = self.temp_dir.path().join(name);
         std::fs::create_dir_all(path.parent().unwrap())?;
         std::fs::write(&path, contents).map(|_| path)
     }
 
+    /// Writes a file with an exact modified time, for tests that depend on a file changing
+    /// without having to wait for the clock to move on.
+    pub fn write_with_modified(
+        &self, name: &str, contents: impl AsRef<[u8]>, modified: SystemTime,
+    ) -> std::io::Result<PathBuf> {
+        let path = self.write(name, contents)?;
+        filetime::set_file_mtime(&path, FileTime::from_system_time(modified))?;
+        Ok(path)
+    }
+
     pub fn delete(&self, name: &str) -> std::io::Result<()> {
         let path = self.temp_dir.path().join(name);
         std::fs::remove_file(path)
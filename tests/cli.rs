@@ -0,0 +1,54 @@
+// The Obsidian config is only found through XDG_CONFIG_HOME on Linux, so elsewhere these would
+// read the real config.
+#![cfg(target_os = "linux")]
+
+use assert_cmd::Command;
+use markdown_db::test::TestDir;
+
+/// The command, with an Obsidian config that has no vaults and an in-memory index, so nothing
+/// outside `config` is read or written.
+fn markdown_db(config: &TestDir) -> Command {
+    let mut command = Command::cargo_bin("markdown-db").unwrap();
+    command
+        .env_clear()
+        .env("XDG_CONFIG_HOME", config.path())
+        .env("XDG_CACHE_HOME", config.path())
+        .arg("--in-memory");
+    command
+}
+
+#[test]
+fn search_limit_tests() -> Result<(), Box<dyn std::error::Error>> {
+    let config = TestDir::new();
+    config.write("obsidian/obsidian.json", r#"{"vaults": {}}"#)?;
+    let limit = |env: Option<&str>, args: &[&str]| -> Option<String> {
+        let mut command = markdown_db(&config);
+        if let Some(limit) = env {
+            command.env("MARKDOWN_DB_LIMIT", limit);
+        }
+        let output = command.args(["search", "hello", "--explain"]).args(args).output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("Limit: ").map(str::to_string))
+    };
+
+    assert_eq!(None, limit(None, &[]), "unlimited by default");
+    assert_eq!(Some("20".into()), limit(Some("20"), &[]));
+    assert_eq!(Some("5".into()), limit(Some("20"), &["--limit", "5"]), "the flag takes precedence");
+    Ok(())
+}
+
+#[test]
+fn no_refresh_url_tests() -> Result<(), Box<dyn std::error::Error>> {
+    let config = TestDir::new();
+    config.write("obsidian/obsidian.json", r#"{"vaults": {}}"#)?;
+    // Nothing listens here, so the search only works if the document isn't downloaded.
+    let url = "http://127.0.0.1:9/unreachable.md";
+
+    let search = |args: &[&str]| markdown_db(&config).args(args).args(["search", "hello"]).output();
+    assert!(search(&["--no-refresh", "--url", url])?.status.success(), "nothing is downloaded");
+    assert!(!search(&["--url", url])?.status.success(), "refreshing downloads the url");
+    Ok(())
+}